@@ -38,6 +38,13 @@ def make_config():
     ntp_servers_f.close()
     f.write("ntp_servers = " + ntp_servers + "\n")
     f.write("buffer_size = 5\n")
+    f.write("time_source = \"ntp\"\n")
+    f.write("rpc_port = " + str(int(port) + 1000) + "\n")
+    f.write("encryption = true\n")
+    signer_auth_path = os.path.join(sys.argv[1], "signer_authorities")
+    signer_auth = open(signer_auth_path, "r")
+    signer_keys = signer_auth.read().split()
+    signer_auth.close()
     ids=range(size)
     ip_list = zip(ids, ip_list)
     del ip_list[nid]
@@ -49,6 +56,7 @@ def make_config():
         f.write("ip = \"" + ip + "\"\n")
         port = addr_list[1]
         f.write("port = " + port + "\n")
+        f.write("signer_public_key = \"" + signer_keys[id] + "\"\n")
     
     #generate keypairs
     signer_auth_path = os.path.join(sys.argv[1], "signer_authorities")
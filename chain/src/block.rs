@@ -10,11 +10,21 @@ use rlp::*;
 use bytes::Bytes;
 
 pub type BlockNumber = u64;
-#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Eq)]
+/// Cached hash of a header. It is derived from the other fields of the header,
+/// so it is never sent over the wire and never taken into account when comparing.
+#[derive(Debug, Clone, Default)]
 pub struct HashWrap(Cell<Option<H256>>);
 
 unsafe impl Sync for HashWrap {}
 
+impl PartialEq for HashWrap {
+    fn eq(&self, _: &HashWrap) -> bool {
+        true
+    }
+}
+
+impl Eq for HashWrap {}
+
 impl Deref for HashWrap {
     type Target = Cell<Option<H256>>;
 
@@ -38,6 +48,7 @@ pub struct Header {
     /// Block receipts root.
     pub receipts_root: H256,
     /// Block hash
+    #[serde(skip_serializing, skip_deserializing)]
     pub hash: HashWrap,
     /// Block proof
     pub proof: Proof,
@@ -63,6 +74,12 @@ impl Header {
         Self::default()
     }
 
+    /// Recovers the public key of the signer.
+    pub fn sign_public(&self) -> Result<H512, Error> {
+        let sig: Signature = self.proof.block_signature.into();
+        recover(&sig, &self.hash()).map_err(|_| Error::InvalidSignature)
+    }
+
     /// verify the proof.
     pub fn verify_proof(&self, anc_hash: H256, pubkey: Vec<u8>, g: Vec<u8>) -> bool {
         let sig = self.proof.time_signature.clone();
@@ -72,7 +89,7 @@ impl Header {
         h1.append(&mut h2);
         h1.append(&mut h3);
         let hash = h1.sha3();
-        bls::verify(hash.to_vec(), sig, pubkey, g)        
+        bls::verify(&hash, &sig, &pubkey, &g).unwrap_or(false)
     }
 
     /// Get difficulty
@@ -80,13 +97,16 @@ impl Header {
         self.proof.time_signature.sha3().into()
     }
 
-    /// Get the hash of this header.
+    /// Get the hash of this header. The block signature is not included,
+    /// so the hash is the same before and after signing and whatever the
+    /// encoding the header was decoded from. Versions before did hash the
+    /// whole RLP, so their databases and peers are incompatible.
     pub fn hash(&self) -> H256 {
         let hash = self.hash.get();
         match hash {
             Some(h) => h,
             None => {
-                let h = self.rlp_hash();
+                let h = self.bare_hash();
                 self.hash.set(Some(h.clone()));
                 h
             }
@@ -118,6 +138,24 @@ impl Header {
 
     }
 
+    /// Get the hash of this header with an empty block signature.
+    pub fn bare_hash(&self) -> H256 {
+        let proof = Proof {
+            time_signature: self.proof.time_signature.clone(),
+            block_signature: H520::default(),
+        };
+        let mut s = RlpStream::new();
+        s.begin_list(7);
+        s.append(&self.parent_hash);
+        s.append(&self.timestamp);
+        s.append(&self.height);
+        s.append(&self.transactions_root);
+        s.append(&self.state_root);
+        s.append(&self.receipts_root);
+        s.append(&proof);
+        s.out().sha3()
+    }
+
     /// Get the RLP of this header.
     pub fn rlp(&self) -> Bytes {
         let mut s = RlpStream::new();
@@ -141,7 +179,7 @@ impl Decodable for Header {
             state_root: r.val_at(4)?,
             receipts_root: r.val_at(5)?,
             proof: r.val_at(6)?,
-            hash: HashWrap(Cell::new(Some(r.as_raw().sha3()))),
+            hash: HashWrap(Cell::new(None)),
         };
 
         Ok(blockheader)
@@ -257,6 +295,26 @@ impl DerefMut for Block {
     }
 }
 
+impl Encodable for Block {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
+        s.append(&self.header);
+        s.append(&self.body);
+    }
+}
+
+impl Decodable for Block {
+    fn decode(r: &UntrustedRlp) -> Result<Self, DecoderError> {
+        if r.item_count()? != 2 {
+            return Err(DecoderError::RlpIncorrectListLen);
+        }
+        Ok(Block {
+            header: r.val_at(0)?,
+            body: r.val_at(1)?,
+        })
+    }
+}
+
 impl Block {
     pub fn new() -> Self {
         Self::default()
@@ -273,21 +331,21 @@ impl Block {
             block_signature: H520::default(),
         };
 
+        let body = Body {
+            transactions: transactions,
+        };
+
         let header = Header {
             parent_hash: parent_hash,
             timestamp: timestamp,
             height: height,
-            transactions_root: SHA3_NULL_RLP,
+            transactions_root: body.transactions_root(),
             state_root: SHA3_NULL_RLP,
             receipts_root: SHA3_NULL_RLP,
             hash: HashWrap(Cell::new(None)),
             proof: proof,
         };
 
-        let body = Body {
-            transactions: transactions,
-        };
-
         Block {
             header: header,
             body: body,
@@ -300,10 +358,12 @@ impl Block {
         self.proof.block_signature = signature;
     }
 
-    /// Recovers the public key of the signer.
-    pub fn sign_public(&self) -> Result<H512, Error> {
-        let sig: Signature = self.proof.block_signature.into();
-        recover(&sig, &self.hash()).map_err(|_| Error::InvalidSignature)
+    /// check the transactions root against the body.
+    pub fn check_transactions_root(&self) -> Result<(), Error> {
+        if self.transactions_root != self.body.transactions_root() {
+            return Err(Error::InvalidTransactionsRoot);
+        }
+        Ok(())
     }
 
     /// Generate the genesis block.
@@ -314,14 +374,14 @@ impl Block {
     }
 
     /// generate proof
-    pub fn gen_proof(private_key: Vec<u8>, time: u64, height: u64, anc_hash: H256 ) -> Vec<u8> {
+    pub fn gen_proof(private_key: Vec<u8>, time: u64, height: u64, anc_hash: H256 ) -> Result<Vec<u8>, bls::Error> {
         let mut h1 = H256::from(time).to_vec();
         let mut h2 = H256::from(height).to_vec();
         let mut h3 = anc_hash.to_vec();
         h1.append(&mut h2);
         h1.append(&mut h3);
         let hash = h1.sha3();
-        bls::sign(hash.to_vec(), private_key)
+        bls::sign(&hash, &private_key)
     }
 }
 
@@ -332,10 +392,10 @@ mod tests {
 
     #[test]
     fn test_proof_public() {
-        let (private_key, public_key, g) = bls::key_gen();
+        let (private_key, public_key, g) = bls::key_gen().unwrap();
         let parent_hash = H256::default();
         let timestamp = 12345;
-        let proof = Block::gen_proof(private_key, timestamp, 1, H256::default());
+        let proof = Block::gen_proof(private_key, timestamp, 1, H256::default()).unwrap();
         let block = Block::init(1, timestamp, parent_hash, Vec::new(), proof);
         assert_eq!(block.verify_proof(H256::default(), public_key, g), true);
     }
@@ -350,4 +410,72 @@ mod tests {
         block.sign(&private_key);
         assert_eq!(block.sign_public().unwrap(), *keypair.pubkey());
     }
+
+    #[test]
+    fn test_hash_after_decode() {
+        let private_key = H256::from("40f2d8f8e1594579824fd04edfc7ff1ddffd6be153b23f4318e1acff037d3ea9",);
+        let keypair = KeyPair::from_privkey(private_key).unwrap();
+        let mut block = Block::init(1, 12345, H256::default(), Vec::new(), vec![1, 2, 3]);
+        let hash = block.hash();
+        block.sign(&private_key);
+
+        let header: Header = decode(&block.header.rlp());
+        assert_eq!(header.hash(), hash);
+        let decoded = Block { header: header, body: Body::default() };
+        assert_eq!(decoded.sign_public().unwrap(), *keypair.pubkey());
+    }
+
+    #[test]
+    fn test_hash_after_serialize() {
+        use bincode::{serialize, deserialize, Infinite};
+        let private_key = H256::from("40f2d8f8e1594579824fd04edfc7ff1ddffd6be153b23f4318e1acff037d3ea9",);
+        let mut block = Block::init(2, 12345, H256::from(1), vec![SignedTransaction::new(1)], vec![1, 2, 3]);
+        let hash = block.hash();
+        block.sign(&private_key);
+        assert!(block.rlp_hash() != hash);
+
+        let decoded: Block = decode(&encode(&block));
+        assert_eq!(decoded.hash(), hash);
+        let rich: RichHeader = decode(&encode(&RichHeader { header: block.header.clone(), verified: true }));
+        assert_eq!(rich.hash(), hash);
+
+        let deserialized: Block = deserialize(&serialize(&block, Infinite).unwrap()).unwrap();
+        assert_eq!(deserialized.hash(), hash);
+        assert_eq!(deserialized, block);
+        assert_eq!(deserialized.sign_public(), block.sign_public());
+    }
+
+    #[test]
+    fn test_transactions_root() {
+        let txs = vec![SignedTransaction::new(1), SignedTransaction::new(2)];
+        let block = Block::init(1, 12345, H256::default(), txs.clone(), Vec::new());
+        assert_eq!(block.transactions_root, complete_merkle_root_raw(txs.iter().map(|t| t.hash()).collect()));
+        assert_eq!(block.check_transactions_root(), Ok(()));
+
+        let empty = Block::init(1, 12345, H256::default(), Vec::new(), Vec::new());
+        assert_eq!(empty.transactions_root, SHA3_NULL_RLP);
+        assert_eq!(empty.check_transactions_root(), Ok(()));
+    }
+
+    #[test]
+    fn test_tampered_body() {
+        let txs = vec![SignedTransaction::new(1), SignedTransaction::new(2)];
+        let private_key = H256::from("40f2d8f8e1594579824fd04edfc7ff1ddffd6be153b23f4318e1acff037d3ea9",);
+        let mut block = Block::init(1, 12345, H256::default(), txs, Vec::new());
+        block.sign(&private_key);
+        let hash = block.hash();
+
+        let mut swapped = block.clone();
+        swapped.body.transactions.reverse();
+        assert_eq!(swapped.hash(), hash);
+        assert_eq!(swapped.check_transactions_root(), Err(Error::InvalidTransactionsRoot));
+
+        let mut appended = block.clone();
+        appended.body.transactions.push(SignedTransaction::new(3));
+        assert_eq!(appended.check_transactions_root(), Err(Error::InvalidTransactionsRoot));
+
+        let mut dropped = block.clone();
+        dropped.body.transactions.clear();
+        assert_eq!(dropped.check_transactions_root(), Err(Error::InvalidTransactionsRoot));
+    }
 }
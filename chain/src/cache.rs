@@ -21,11 +21,13 @@ pub struct CacheSize {
     pub blocks: usize,
     /// Transaction addresses cache size.
     pub transaction_addresses: usize,
+    /// Block receipts cache size.
+    pub block_receipts: usize,
 }
 
 impl CacheSize {
     /// Total amount used by the cache.
     pub fn total(&self) -> usize {
-        self.blocks + self.transaction_addresses
+        self.blocks + self.transaction_addresses + self.block_receipts
     }
 }
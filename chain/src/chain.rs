@@ -1,16 +1,26 @@
 use parking_lot::{Mutex, RwLock};
 use util::hash::H256;
 use util::Hashable;
-use std::collections::{HashMap, HashSet, VecDeque};
-use rand::{thread_rng, Rng};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use util::config::SleepyConfig;
-use std::sync::mpsc::{Sender, channel};
+use std::sync::mpsc::{Sender, Receiver, channel};
 use std::thread;
 use std::sync::Arc;
 use std::time::Duration;
-use block::{Block, Body, RichHeader, BlockNumber};
+use block::{Block, Body, Header, RichHeader, BlockNumber};
 use transaction::SignedTransaction;
+use state::State;
+use receipt::{Receipt, BlockReceipts};
 use error::*;
+use event::ChainEvent;
+use fork_choice::ForkChoice;
+use genesis;
+use validators::{self, ValidatorSet};
+use difficulty::Epoch;
+use evidence::Evidence;
+use util::{H512, U256};
+use rlp;
 use kvdb::{DBTransaction, KeyValueDB};
 use cache_manager::CacheManager;
 use extras::*;
@@ -24,6 +34,7 @@ enum CacheId {
     BlockBody(H256),
     BlockHashes(BlockNumber),
     TransactionAddresses(H256),
+    BlockReceipts(H256),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -115,16 +126,6 @@ impl HashCache {
         self.queue.push_back(b);
     }
 
-    pub fn drop_back(&mut self, m: usize) {
-        let bs: Vec<BlockInfo> = self.queue.drain(m..).collect();
-        for b in bs {
-            let txs = b.transactions;
-            for h in txs {
-                self.hashes.remove(&h);
-            }
-        }
-    }
-
     pub fn replace(&mut self, i: usize, b: BlockInfo) {
         let txs = b.transactions.clone();
         let height = b.height;
@@ -164,6 +165,7 @@ pub struct Chain {
     //extra caches
     transaction_addresses: RwLock<HashMap<H256, TransactionAddress>>,
     block_hashes: RwLock<HashMap<BlockNumber,H256>>,
+    block_receipts: RwLock<HashMap<H256, BlockReceipts>>,
 
     future_blocks: RwLock<Vec<Block>>,
     unknown_parent: RwLock<HashMap<H256, Vec<Block>>>,
@@ -173,18 +175,42 @@ pub struct Chain {
     txs_cache: RwLock<HashCache>,
 
     config: Arc<RwLock<SleepyConfig>>,
+    fork_choice: ForkChoice,
+    /// Difficulty of the epochs by the hash of the last block before them.
+    epochs: RwLock<HashMap<H256, Epoch>>,
+    /// Validators with recorded equivocation evidence. The evidence is only
+    /// kept and gossiped, the validator set does not depend on it.
+    offenders: RwLock<HashSet<H512>>,
     sender: Mutex<Sender<H256>>,
+    subscribers: Mutex<Vec<Sender<ChainEvent>>>,
 }
 
+/// Height of the block whose proof seeds the proofs of the block at `height`:
+/// the first block of the epoch before.
+pub fn anc_height(height: u64, epoch_len: u64) -> u64 {
+    let mut a = height / epoch_len;
+    if a > 0 { a -= 1}
+    a * epoch_len
+}
+
+/// Blocks kept in `txs_cache` besides `buffer_size`.
+const TXS_HISTORY: u64 = 100;
+
 //TODO use more efficient  way to check duplicated transactions.
 
 impl Chain {
-    pub fn init(config: Arc<RwLock<SleepyConfig>>, db: Arc<KeyValueDB>) -> Arc<Self> {
+    /// Load the best chain from `db`, or start it with the genesis block.
+    /// Fails if the recent blocks are missing, `fsck` can repair that.
+    pub fn init(config: Arc<RwLock<SleepyConfig>>, db: Arc<KeyValueDB>) -> Result<Arc<Self>, Error> {
         let (sender, receiver) = channel();
         // 400 is the avarage size of the key
         let cache_man = CacheManager::new(1 << 14, 1 << 20, 400);
-        let lmt = 100u64;
+        let lmt = TXS_HISTORY;
         let bs = {config.read().buffer_size};
+        let fork_choice = {
+            let config = config.read();
+            config.fork_choice.parse().map_err(|_| Error::UnknownForkChoice(config.fork_choice.clone()))?
+        };
        
         let chain = Arc::new(Chain {
                                 db: db.clone(),
@@ -195,60 +221,77 @@ impl Chain {
                                 unknown_parent: RwLock::new(HashMap::new()),
                                 transaction_addresses: RwLock::new(HashMap::new()),
                                 block_hashes: RwLock::new(HashMap::new()),
+                                block_receipts: RwLock::new(HashMap::new()),
                                 current_height: RwLock::new(0),
                                 current_hash: RwLock::new(H256::default()),
 
                                 txs_cache: RwLock::new(HashCache::new((lmt+bs+5) as usize, (lmt+bs+1) as usize)),
 
                                 config: config,
+                                fork_choice: fork_choice,
+                                epochs: RwLock::new(HashMap::new()),
+                                offenders: RwLock::new(HashSet::new()),
                                 sender: Mutex::new(sender),
+                                subscribers: Mutex::new(Vec::new()),
                              });
 
+        let mut genesis_batch = chain.db.transaction();
+        let genesis = {
+            let config = chain.config.read();
+            match config.genesis {
+                Some(ref spec) => genesis::build(spec, chain.db.clone(), &mut genesis_batch)?,
+                None => Block::genesis(config.start_time()),
+            }
+        };
+        genesis_batch.put(db::COL_EXTRA, b"genesis_hash", &genesis.hash());
+
         let ret = chain.db.get(db::COL_EXTRA, b"current_hash").unwrap();
         
         match ret {
             Some(hash) => {
-                let mut txs_cache = chain.txs_cache.write();
-                let hash = H256::from_slice(&hash);
-                info!("{}", hash);
-                let mut header = chain.get_block_header_by_hash(&hash).expect("header not found!");
-                let mut current_height = chain.current_height.write();
-                let mut current_hash = chain.current_hash.write();
-                
-                *current_height = header.height;
-                *current_hash = hash;
-
-                let mut n = lmt+bs+1;
-                loop {
-                    let txs_hashes = chain.block_transaction_hashes_by_hash(&header.hash());
-                    txs_cache.push_front(BlockInfo{hash: header.hash(), height: header.height, timestamp: header.timestamp, transactions: txs_hashes.clone()});
-
-                    n -= 1;
-                    if n == 0 || header.height == 0{
-                        break;
-                    }
-
-                    header = chain.get_block_header_by_hash(&header.parent_hash).expect("header not found!");
+                // databases of earlier versions do not record the genesis hash,
+                // and store their blocks by another header hash
+                let initialized = match chain.db.get(db::COL_EXTRA, b"genesis_hash").unwrap() {
+                    Some(h) => H256::from_slice(&h),
+                    None => return Err(Error::OutdatedDatabase),
+                };
+                if initialized != genesis.hash() {
+                    warn!("database was initialized with genesis {:?}, expected {:?}", initialized, genesis.hash());
+                    return Err(Error::GenesisMismatch);
                 }
+                chain.db.write(genesis_batch).expect("DB write failed.");
 
-                for _ in 0..n {
-                    txs_cache.push_back(BlockInfo{hash: header.hash(), height: 0, timestamp: header.timestamp, transactions: Vec::new()});
-                }
+                let hash = H256::from_slice(&hash);
+                info!("{}", hash);
+                let header = chain.get_block_header_by_hash(&hash).ok_or(Error::InconsistentDatabase)?;
+                *chain.current_height.write() = header.height;
+                *chain.current_hash.write() = hash;
+                *chain.txs_cache.write() = chain.txs_cache_at(hash)?;
             }
             None => {
-                let t = chain.config.read().start_time();
-                let genesis = Block::genesis(t);
+                let t = genesis.timestamp;
                 {
                     let mut txs_cache = chain.txs_cache.write();
                     for _ in 0..(lmt+bs+1) {
                         txs_cache.push_back(BlockInfo{hash: genesis.hash(), height: 0, timestamp: t, transactions: Vec::new()});
                     }
                 }
-                chain.insert_at(genesis, true);
+                chain.insert_at(genesis_batch, genesis, true);
             }
 
         }
 
+        {
+            let prefix = [ExtrasIndex::Evidence as u8];
+            let mut offenders = chain.offenders.write();
+            for (key, _) in chain.db.iter_from_prefix(db::COL_EXTRA, &prefix) {
+                if key.len() != 65 || key[0] != prefix[0] {
+                    break;
+                }
+                offenders.insert(H512::from_slice(&key[1..]));
+            }
+        }
+
         let mario = chain.clone();
         thread::spawn(move || loop {
                 let hash = receiver.recv().unwrap();
@@ -269,7 +312,7 @@ impl Chain {
                 subtask.handle_pending();
             }
         });
-        chain
+        Ok(chain)
     }
 
     fn save_status(&self, batch: &mut DBTransaction, height: BlockNumber, hash: H256) {
@@ -283,11 +326,10 @@ impl Chain {
 
     }
 
-    fn insert_at(&self, block: Block, verified: bool) {
+    fn insert_at(&self, mut batch: DBTransaction, block: Block, verified: bool) {
         let hash = block.hash();
         let height = block.height;
 
-        let mut batch = self.db.transaction();
         let rh = RichHeader {header: block.header, verified: verified};
 
         { 
@@ -298,34 +340,174 @@ impl Chain {
             let mut write_bodies = self.block_bodies.write();
             batch.write_with_cache(db::COL_BODIES, &mut *write_bodies, hash, block.body, CacheUpdatePolicy::Overwrite);
         }
+        batch.put(db::COL_EXTRA, &block_at_height_key(height, &hash), &[]);
+
+        let mut events = Vec::new();
+
+        if self.is_new_best(&rh) {
+            match self.adjust_block_hashes(&mut batch, rh) {
+                Some(e) => {
+                    events = e;
+                    self.save_status(&mut batch, height, hash);
+                }
+                None => info!("Switch Long Fork Error {:?} {:?}", height, hash),
+            }
+        }
 
-        let mut rng = thread_rng();
+        self.db.write(batch).expect("DB write failed.");
+
+        for event in events {
+            self.notify(event);
+        }
+    }
 
+    /// Whether `header` should become the best block by the fork choice rule.
+    /// Only a block at the height of the best block or the next one can.
+    pub fn is_new_best(&self, header: &Header) -> bool {
         let current_height = { *self.current_height.read() };
         let current_hash = { *self.current_hash.read() };
+        if current_hash == H256::default() {
+            return true;
+        }
+        if header.height > current_height + 1 {
+            return false;
+        }
+        if !self.is_above_finalized(header) {
+            info!("refuse fork below finalized block {:?} {:?}", header.height, header.hash());
+            return false;
+        }
+        match self.get_block_header_by_hash(&current_hash) {
+            Some(current) => self.fork_choice.prefer(header, &current),
+            None => true,
+        }
+    }
 
-        if height == current_height + 1 
-           || (height == current_height && (rng.gen_range(0, 1) == 0 || current_hash == H256::default())) {
-           
-            if self.adjust_block_hashes(&mut batch, rh) {
-                self.save_status(&mut batch, height, hash);
-            } else {
-                info!("Switch Long Fork Error {:?} {:?}", height, hash);
+    /// Whether the fork of `header` branches off the best chain at or above the finalized block.
+    fn is_above_finalized(&self, header: &Header) -> bool {
+        if header.height == 0 {
+            return false;
+        }
+        let (finalized, _) = self.finalized();
+        let mut height = header.height - 1;
+        let mut hash = header.parent_hash;
+        loop {
+            if self.block_hash_by_number(height) == Some(hash) {
+                return height >= finalized;
+            }
+            if height <= finalized {
+                return false;
+            }
+            match self.get_block_header_by_hash(&hash) {
+                Some(h) => hash = h.parent_hash,
+                None => return false,
             }
+            height -= 1;
         }
+    }
 
-        self.db.write(batch).expect("DB write failed.");
+    /// Height and hash of the last final block, the one `confirmations` below the best block.
+    pub fn finalized(&self) -> (u64, H256) {
+        let depth = { self.config.read().get_confirmations() };
+        let (height, hash) = self.get_status();
+        let finalized = height.saturating_sub(depth);
+        if finalized == height {
+            return (height, hash);
+        }
+        (finalized, self.block_hash_by_number(finalized).unwrap_or_default())
+    }
+
+    /// Number of blocks from the block `hash` to the best block, both included,
+    /// `None` if the block isn't in the best chain.
+    pub fn block_confirmations(&self, hash: &H256) -> Option<u64> {
+        let header = match self.get_block_header_by_hash(hash) {
+            Some(h) => h,
+            None => return None,
+        };
+        if self.block_hash_by_number(header.height) != Some(*hash) {
+            return None;
+        }
+        let (height, _) = self.get_status();
+        if header.height > height {
+            return None;
+        }
+        Some(height - header.height + 1)
+    }
+
+    /// Number of confirmations of the block including transaction `hash`.
+    pub fn transaction_confirmations(&self, hash: &H256) -> Option<u64> {
+        self.get_transaction_address(hash).and_then(|address| self.block_confirmations(&address.block_hash))
+    }
 
+    /// Whether the block `hash` is in the best chain at or below the finalized block.
+    pub fn is_finalized(&self, hash: &H256) -> bool {
+        let depth = { self.config.read().get_confirmations() };
+        match self.block_confirmations(hash) {
+            Some(n) => n > depth,
+            None => false,
+        }
+    }
+
+    /// Receive the events of the best chain from now on.
+    pub fn subscribe(&self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().push(sender);
+        receiver
+    }
+
+    fn notify(&self, event: ChainEvent) {
+        trace!("chain event {:?}", event);
+        self.subscribers.lock().retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    /// Events of `blocks` joining the best chain, the last of them becoming the best block.
+    /// Must be called before the block hashes are updated.
+    fn best_chain_events(&self, blocks: &[BlockInfo]) -> Vec<ChainEvent> {
+        let enacted: BTreeMap<u64, &BlockInfo> = blocks.iter().map(|b| (b.height, b)).collect();
+        let mut events = Vec::new();
+
+        let retracted: Vec<H256> = enacted.values()
+            .filter_map(|b| match self.block_hash_by_number(b.height) {
+                Some(hash) if hash != b.hash => Some(hash),
+                _ => None,
+            })
+            .collect();
+        if !retracted.is_empty() {
+            events.push(ChainEvent::Reorg {
+                retracted: retracted,
+                enacted: enacted.values().map(|b| b.hash).collect(),
+            });
+        }
+
+        for b in enacted.values() {
+            for (i, h) in b.transactions.iter().enumerate() {
+                events.push(ChainEvent::TxIncluded { hash: *h, block_hash: b.hash, index: i });
+            }
+        }
+
+        if let Some(best) = enacted.values().next_back() {
+            events.push(ChainEvent::NewBest { height: best.height, hash: best.hash });
+        }
+        events
     }
 
     pub fn insert(&self, block: Block) -> Result<(), Error> {
         let hash = block.hash();
 
         self.block_basic_check(&block)?;
+
+        // both blocks are kept, the fork choice picks one of them
+        if let Some(evidence) = self.find_equivocation(&block) {
+            if let Err(err) = self.add_evidence(evidence) {
+                warn!("drop evidence {:?}", err);
+            }
+        }
         
         let checked = self.check_transactions(&block)?;
-        
-        self.insert_at(block, checked);
+
+        let mut batch = self.db.transaction();
+        self.check_execution(&block, &mut batch)?;
+
+        self.insert_at(batch, block, checked);
 
         self.sender.lock().send(hash).unwrap();
 
@@ -334,9 +516,7 @@ impl Chain {
 
     pub fn anc_height(&self, height: u64) -> u64 {
         let len = {self.config.read().epoch_len};
-        let mut a = height / len;
-        if a > 0 { a -= 1}
-        a * len
+        anc_height(height, len)
     }
 
     pub fn anc_hash(&self, height: u64, hash: H256) -> Option<H256> {
@@ -347,6 +527,121 @@ impl Chain {
         }
     }
 
+    /// The validator set of the block at `height` on top of `parent_hash`:
+    /// the set as of the end of the epoch before.
+    pub fn validators(&self, height: u64, parent_hash: H256) -> Result<ValidatorSet, Error> {
+        let (epoch_len, initial) = {
+            let config = self.config.read();
+            (config.epoch_len, config.public_keys.clone())
+        };
+        let number = validators::set_height(height, epoch_len);
+        let header = self.block_hash_by_number_fork(number, height - 1, parent_hash)
+            .and_then(|hash| self.get_block_header_by_hash(&hash))
+            .ok_or(Error::UnknownAncestor)?;
+        validators::at(self.db.clone(), header.state_root, &initial)
+    }
+
+    /// A stored block signed by the signer of `block` for the same slot.
+    fn find_equivocation(&self, block: &Block) -> Option<Evidence> {
+        let hash = block.hash();
+        let signer = match block.sign_public() {
+            Ok(signer) => signer,
+            Err(_) => return None,
+        };
+        let prefix = block_at_height_key(block.height, &H256::default());
+        for (key, _) in self.db.iter_from_prefix(db::COL_EXTRA, &prefix[..9]) {
+            let other = match decode_block_at_height_key(&key) {
+                Some((height, other)) if height == block.height => other,
+                _ => break,
+            };
+            if other == hash {
+                continue;
+            }
+            if let Some(header) = self.get_block_header_by_hash(&other) {
+                if header.timestamp == block.timestamp && header.sign_public() == Ok(signer) {
+                    return Some(Evidence::new(header.header.clone(), block.header.clone()));
+                }
+            }
+        }
+        None
+    }
+
+    /// Record the evidence and notify the subscribers. Returns whether it is new.
+    pub fn add_evidence(&self, evidence: Evidence) -> Result<bool, Error> {
+        let offender = evidence.offender()?;
+        if self.offenders.read().contains(&offender) {
+            return Ok(false);
+        }
+        if !self.validators(evidence.first.height, evidence.first.parent_hash)?.contains_key(&offender) {
+            return Err(Error::NotValidator);
+        }
+        if !self.offenders.write().insert(offender) {
+            return Ok(false);
+        }
+        let (first, second) = evidence.hashes();
+        warn!("validator {:?} signed both {:?} and {:?} at height {}", offender, first, second, evidence.first.height);
+
+        let mut batch = self.db.transaction();
+        batch.put(db::COL_EXTRA, &evidence_key(&offender), &rlp::encode(&evidence));
+        self.db.write(batch).expect("DB write failed.");
+        self.notify(ChainEvent::Equivocation(evidence));
+        Ok(true)
+    }
+
+    /// The evidence against `offender`.
+    pub fn evidence(&self, offender: &H512) -> Option<Evidence> {
+        self.db.get(db::COL_EXTRA, &evidence_key(offender)).expect("db get failed")
+            .map(|data| rlp::decode(&data))
+    }
+
+    /// Proof target of the block at `height` on top of `parent_hash`.
+    pub fn difficulty(&self, height: u64, parent_hash: H256) -> Result<U256, Error> {
+        let epoch_len = {self.config.read().epoch_len};
+        let anchor = self.block_hash_by_number_fork(validators::set_height(height, epoch_len), height - 1, parent_hash)
+            .ok_or(Error::UnknownAncestor)?;
+        self.epoch(height / epoch_len * epoch_len, anchor).map(|epoch| epoch.target)
+    }
+
+    /// The epoch starting at height `start`, after block `anchor`.
+    fn epoch(&self, mut start: u64, mut anchor: H256) -> Result<Epoch, Error> {
+        let (epoch_len, steps, nps, adjustment, initial) = {
+            let config = self.config.read();
+            (config.epoch_len, config.steps, config.nps, config.difficulty_adjustment, config.public_keys.clone())
+        };
+
+        // walk back to an epoch which is known, or which is not adjusted
+        let mut previous = None;
+        let mut pending = Vec::new();
+        loop {
+            if let Some(epoch) = self.epochs.read().get(&anchor) {
+                previous = Some(*epoch);
+                break;
+            }
+            let header = self.get_block_header_by_hash(&anchor).ok_or(Error::UnknownAncestor)?;
+            pending.push((start, header));
+            if !adjustment || start < 2 * epoch_len {
+                break;
+            }
+            anchor = self.block_hash_by_number_fork(start - 1 - epoch_len, start - 1, anchor).ok_or(Error::UnknownAncestor)?;
+            start -= epoch_len;
+        }
+
+        while let Some((start, header)) = pending.pop() {
+            let validators = validators::at(self.db.clone(), header.state_root, &initial)?.len();
+            let epoch = match previous {
+                Some(ref p) if adjustment && start >= 2 * epoch_len => p.next(validators, header.timestamp, epoch_len, steps, nps),
+                _ => Epoch::new(validators, header.timestamp, steps, nps),
+            };
+            let mut epochs = self.epochs.write();
+            if epochs.len() > 1024 {
+                epochs.clear();
+            }
+            epochs.insert(header.hash(), epoch);
+            previous = Some(epoch);
+        }
+        Ok(previous.expect("at least one epoch is computed or known"))
+    }
+
     pub fn tx_basic_check(&self, stx: &SignedTransaction) -> Result<(), Error> {
         stx.recover_public()?;
         Ok(())
@@ -355,13 +650,9 @@ impl Chain {
     pub fn block_basic_check(&self, block: &Block) -> Result<(), Error> {
         let hash = block.hash();
 
-        if block.difficulty() > self.config.read().get_difficulty() {
-            return Err(Error::InvalidProof);
-        }
-
         let config = self.config.read();
 
-        let now = match config.ntp_now() {
+        let now = match config.now() {
             Some(t) => t,
             _ => return Err(Error::NTPError),
         };
@@ -376,6 +667,8 @@ impl Chain {
             return Err(Error::DuplicateBlock);
         }
 
+        block.check_transactions_root()?;
+
         match self.get_block_header_by_hash(&block.parent_hash) {
             Some(h) => {
                 if block.timestamp <= h.timestamp {
@@ -394,11 +687,16 @@ impl Chain {
             self.future_blocks.write().push(block.clone());
             return Err(Error::FutureBlock);
         }
+        drop(config);
         
         let height = block.height;
         let anc_hash = self.anc_hash(height - 1, block.parent_hash).ok_or(Error::UnknownAncestor)?;
+        if block.difficulty() > self.difficulty(height, block.parent_hash)? {
+            return Err(Error::InvalidProof);
+        }
         let sign_pub = block.sign_public()?;
-        let (proof_pub, proof_g) = config.get_proof_pub(&sign_pub).ok_or(Error::InvalidPublicKey)?;
+        let validators = self.validators(height, block.parent_hash)?;
+        let (proof_pub, proof_g) = validators.get(&sign_pub).cloned().ok_or(Error::InvalidPublicKey)?;
 
         if !block.verify_proof(anc_hash, proof_pub, proof_g) {
             return Err(Error::InvalidProofKey);
@@ -446,7 +744,7 @@ impl Chain {
     }
 
     pub fn get_left_bound(&self, height: u64, txs_cache: &HashCache) -> (u64, u64) {
-        let ch = {*self.current_height.read()};
+        let ch = txs_cache.best_height();
         let nps = {self.config.read().nps};
         let bs = {self.config.read().buffer_size};
         let i = bs + height - ch;
@@ -457,6 +755,13 @@ impl Chain {
 
     }
 
+    /// Timestamp at or before which transactions can no longer be included on the best chain.
+    pub fn expire_timestamp(&self) -> u64 {
+        let txs_cache = self.txs_cache.read();
+        let height = self.current_height();
+        self.get_left_bound(height, &txs_cache).1
+    }
+
     pub fn check_transactions(&self, block: &Block) -> Result<bool, Error> {
         let (height, txs_set) = match self.transactions_diff(block.height - 1, block.parent_hash) {
             Ok((h, t)) => (h, t),
@@ -496,19 +801,86 @@ impl Chain {
 
     }
 
-    pub fn gen_block(&self, height: u64, hash: H256, time: u64, time_sig: Vec<u8>, txs: Vec<SignedTransaction>) -> Block {
+    /// Build, sign and store a block of `txs` on top of block `hash` at `height`.
+    /// Fails without storing anything if the transactions can not be executed.
+    pub fn gen_block(&self, height: u64, hash: H256, time: u64, time_sig: Vec<u8>, txs: Vec<SignedTransaction>) -> Result<Block, Error> {
         
         let txs = self.filter_transactions(height, hash, txs);
 
         let signer_private_key = {self.config.read().get_signer_private_key()};
 
         let mut block = Block::init(height + 1, time, hash, txs, time_sig);
-        
+
+        let mut batch = self.db.transaction();
+        let (state_root, receipts) = self.execute_transactions(&hash, height + 1, &block.body.transactions, &mut batch)?;
+        block.state_root = state_root;
+        block.receipts_root = receipts.receipts_root();
+
         block.sign(&signer_private_key);
 
-        self.insert_at(block.clone(), true);
+        self.write_receipts(&mut batch, block.hash(), receipts);
+
+        self.insert_at(batch, block.clone(), true);
+
+        Ok(block)
+    }
+
+    /// Execute `txs` of the block at `height` on top of the state of block `parent_hash`.
+    /// The new state nodes are written into `batch`; the new state root and the receipts are returned.
+    pub fn execute_transactions(&self, parent_hash: &H256, height: u64, txs: &[SignedTransaction], batch: &mut DBTransaction) -> Result<(H256, BlockReceipts), Error> {
+        let parent = self.get_block_header_by_hash(parent_hash).ok_or(Error::UnknownParent)?;
+        let mut state = State::from_existing(self.db.clone(), parent.state_root)?;
+        let initial = self.config.read().public_keys.clone();
+        let mut receipts = Vec::with_capacity(txs.len());
+        for (i, tx) in txs.iter().enumerate() {
+            let (success, logs) = match state.apply_transaction(tx, &initial) {
+                Err(Error::UnknownState) => return Err(Error::UnknownState),
+                Err(err) => {
+                    trace!("transaction {:?} failed {:?}", tx.hash(), err);
+                    (false, Vec::new())
+                }
+                Ok(logs) => (true, logs),
+            };
+            receipts.push(Receipt {
+                transaction_hash: tx.hash(),
+                block_height: height,
+                index: i,
+                success: success,
+                logs: logs,
+            });
+        }
+        state.commit(batch);
+        Ok((state.root(), BlockReceipts::new(receipts)))
+    }
+
+    /// Re-execute the block and check its state and receipts roots.
+    pub fn check_execution(&self, block: &Block, batch: &mut DBTransaction) -> Result<(), Error> {
+        let (state_root, receipts) = self.execute_transactions(&block.parent_hash, block.height, &block.body.transactions, batch)?;
+        if state_root != block.state_root {
+            return Err(Error::InvalidStateRoot);
+        }
+        if receipts.receipts_root() != block.receipts_root {
+            return Err(Error::InvalidReceiptsRoot);
+        }
+        self.write_receipts(batch, block.hash(), receipts);
+        Ok(())
+    }
+
+    fn write_receipts(&self, batch: &mut DBTransaction, hash: H256, receipts: BlockReceipts) {
+        let mut block_receipts = self.block_receipts.write();
+        batch.write_with_cache(db::COL_RECEIPTS, &mut *block_receipts, hash, receipts, CacheUpdatePolicy::Overwrite);
+        self.cache_man.lock().note_used(CacheId::BlockReceipts(hash));
+    }
+
+    /// Get the state after executing the block `hash`.
+    pub fn state_at(&self, hash: &H256) -> Option<State> {
+        self.get_block_header_by_hash(hash).and_then(|header| State::from_existing(self.db.clone(), header.state_root).ok())
+    }
 
-        block
+    /// Get the state of the current best block.
+    pub fn best_state(&self) -> Option<State> {
+        let hash = { *self.current_hash.read() };
+        self.state_at(&hash)
     }
 
     pub fn get_status(&self) -> (u64, H256) {
@@ -594,6 +966,20 @@ impl Chain {
         result
     }
 
+    /// Get the receipts of the block with given hash.
+    pub fn get_block_receipts(&self, hash: &H256) -> Option<BlockReceipts> {
+        let result = self.db.read_with_cache(db::COL_RECEIPTS, &self.block_receipts, hash);
+        self.cache_man.lock().note_used(CacheId::BlockReceipts(hash.clone()));
+        result
+    }
+
+    /// Get the receipt of the transaction with given hash.
+    pub fn get_transaction_receipt(&self, hash: &H256) -> Option<Receipt> {
+        self.get_transaction_address(hash).and_then(|addr| {
+            self.get_block_receipts(&addr.block_hash).and_then(|r| r.receipts.get(addr.index).cloned())
+        })
+    }
+
     fn get_unverif_headers(&self, mut header: RichHeader) -> Vec<RichHeader> {
         let mut headers: Vec<RichHeader> = Vec::new();
         loop {
@@ -625,6 +1011,29 @@ impl Chain {
         blocks
     }
 
+    /// The transactions cache of the chain ending with block `hash`,
+    /// padded with the genesis block below the first block.
+    fn txs_cache_at(&self, hash: H256) -> Result<HashCache, Error> {
+        let n = (TXS_HISTORY + {self.config.read().buffer_size} + 1) as usize;
+        let mut txs_cache = HashCache::new(n + 4, n);
+        let mut header = self.get_block_header_by_hash(&hash).ok_or(Error::InconsistentDatabase)?;
+        loop {
+            let txs_hashes: Vec<H256> = self.get_block_body_by_hash(&header.hash())
+                .ok_or(Error::InconsistentDatabase)?
+                .transactions.iter().map(|t| t.hash()).collect();
+            txs_cache.push_front(BlockInfo{hash: header.hash(), height: header.height, timestamp: header.timestamp, transactions: txs_hashes});
+
+            if txs_cache.len() == n || header.height == 0 {
+                break;
+            }
+            header = self.get_block_header_by_hash(&header.parent_hash).ok_or(Error::InconsistentDatabase)?;
+        }
+        while txs_cache.len() < n {
+            txs_cache.push_front(BlockInfo{hash: header.hash(), height: 0, timestamp: header.timestamp, transactions: Vec::new()});
+        }
+        Ok(txs_cache)
+    }
+
     pub fn update_txs_cache(&self, txs_cache: &mut HashCache, blocks: Vec<BlockInfo>, m: usize) {
         let mut i = 1usize;
         for b in blocks {
@@ -636,18 +1045,6 @@ impl Chain {
         }
     }
 
-    pub fn tx_cache_roll_back(&self, txs_cache: &mut HashCache, hash: H256, n: usize) {
-        txs_cache.drop_back(n);
-        let mut header = self.get_block_header_by_hash(&hash).unwrap();
-        for _ in 0..n {
-            if header.height != 0 {
-                header = self.get_block_header_by_hash(&header.parent_hash).unwrap();
-            }
-            let tx_hashes = self.block_transaction_hashes_by_hash(&header.hash());
-            txs_cache.push_front(BlockInfo{hash: header.hash(), height: header.height, timestamp: header.timestamp, transactions: tx_hashes});
-        }
-    }
-
     pub fn transactions_check(&self, txs: &Vec<SignedTransaction>, mut txs_set: HashSet<H256>, txs_cache: &HashCache, height: u64, max: u64) -> Result<(), Error> {
         let (bh, bt) = self.get_left_bound(height - 1, txs_cache);
         for tx in txs {
@@ -668,38 +1065,15 @@ impl Chain {
         Ok(())
     }
     
-    pub fn switch_long_fork(&self, batch: &mut DBTransaction, header: RichHeader) -> Result<(), Error> {
-        let mut txs_cache = {self.txs_cache.read().clone()};
-        let current_height = {*self.current_height.read()};
-        // let best = header.height;
-        
+    pub fn switch_long_fork(&self, batch: &mut DBTransaction, header: RichHeader) -> Result<Vec<ChainEvent>, Error> {
         let mut headers = self.get_unverif_headers(header.clone());
+        headers.reverse();
 
-        let mut n = headers.len();
-
-        if current_height != header.height {
-            n -= 1;
-        }
-        
-        let to = txs_cache.total();
-        //get fork headers
-        let mut fork_blocks = self.get_fork_chain(header.height, header.hash());
-        
-        //get the last header
-        let hash = match n < to {
-            true => {
-                self.update_txs_cache(&mut txs_cache, fork_blocks.clone(), to - n);
-                txs_cache.queue.front().unwrap().hash
-            },
-            false => {
-                n = to;
-                headers.last().unwrap().hash()
-            }
-        };
-
-        self.tx_cache_roll_back(&mut txs_cache, hash, n);
+        //the checked part of the fork, and the transactions cache at its end
+        let (height, hash) = (headers[0].height - 1, headers[0].parent_hash);
+        let mut fork_blocks = self.get_fork_chain(height, hash);
+        let mut txs_cache = self.txs_cache_at(hash)?;
 
-        headers.reverse();
         for mut header in headers {
 
             let txs = self.get_block_body_by_hash(&header.hash()).expect("invalid block").transactions;
@@ -708,6 +1082,9 @@ impl Chain {
             //check transactions
             self.transactions_check(&txs, HashSet::new(), &txs_cache, header.height, header.height)?;
 
+            //state is never pruned, so the new head only needs its state root to be available
+            State::from_existing(self.db.clone(), header.state_root)?;
+
             //mark header as verified
             {   
                 header.verified = true;
@@ -722,6 +1099,7 @@ impl Chain {
 
         }
 
+        let events = self.best_chain_events(&fork_blocks);
         self.update_transaction_addresses(batch, fork_blocks.clone());
         self.update_block_number(batch, fork_blocks);
 
@@ -733,7 +1111,7 @@ impl Chain {
 
         self.print_chain(header.height);
 
-        Ok(())
+        Ok(events)
     }
 
     pub fn block_transaction_hashes_by_height(&self, height: u64) -> Vec<H256> {
@@ -802,11 +1180,14 @@ impl Chain {
     }
 
     //TODO: get old hash from queue
-    pub fn adjust_block_hashes(&self, batch: &mut DBTransaction, header: RichHeader) -> bool {
+    /// Make `header` the best block, `None` if its fork is invalid.
+    /// Callers check `is_new_best` first.
+    /// Returns the events of the change, to be sent once `batch` is written.
+    pub fn adjust_block_hashes(&self, batch: &mut DBTransaction, header: RichHeader) -> Option<Vec<ChainEvent>> {
         info!("begin adjust best blocks {:?} {:?}", header.height, header.hash());
 
         if !header.verified {
-            return self.switch_long_fork(batch, header).is_ok();
+            return self.switch_long_fork(batch, header).ok();
         }
 
         let mut fork_blocks = match header.height > 0 {
@@ -815,8 +1196,10 @@ impl Chain {
         };
 
         let txs_hashes = self.block_transaction_hashes_by_hash(&header.hash());
-        fork_blocks.push(BlockInfo{hash: header.hash(), height: header.height, timestamp: header.timestamp, transactions: txs_hashes.clone()});
+        //highest first, as `update_txs_cache` expects
+        fork_blocks.insert(0, BlockInfo{hash: header.hash(), height: header.height, timestamp: header.timestamp, transactions: txs_hashes.clone()});
         
+        let events = self.best_chain_events(&fork_blocks);
         self.update_transaction_addresses(batch, fork_blocks.clone());
 
         self.update_block_number(batch, fork_blocks.clone());
@@ -834,7 +1217,7 @@ impl Chain {
         }
         
         self.print_chain(header.height);
-        true
+        Some(events)
     }
 
     fn print_chain(&self, best: u64) {
@@ -854,11 +1237,13 @@ impl Chain {
     }
 
     fn handle_pending(&self) {
-        if let Some(now) = self.config.read().ntp_now() {
+        if let Some(now) = self.config.read().now() {
 
             let left: Vec<Block> = self.future_blocks.read().clone().into_iter().filter(|b| {
                 if b.timestamp <= now {
-                    self.insert(b.clone()).expect("insert block failed");
+                    if let Err(err) = self.insert(b.clone()) {
+                        warn!("drop future block {:?}: {:?}", b.hash(), err);
+                    }
                     false
                 } else {
                     true
@@ -874,10 +1259,68 @@ impl Chain {
         CacheSize {
             blocks: self.block_headers.read().heap_size_of_children() + self.block_bodies.read().heap_size_of_children(),
             transaction_addresses: self.transaction_addresses.read().heap_size_of_children(),
+            block_receipts: self.block_receipts.read().heap_size_of_children(),
         }
     }
 
     /// Ticks our cache system and throws out any old data.
+    /// In pruning mode, delete the bodies of the canonical blocks before the
+    /// last `pruning` ones, and the blocks of forks below the finalized block,
+    /// which can't be switched to any more. Only blocks stored while pruning
+    /// was enabled are deleted. The state is never pruned: its nodes are shared
+    /// between blocks, so those of a deleted block may still be in use.
+    pub fn prune(&self) {
+        let (keep, bs) = {
+            let config = self.config.read();
+            match config.pruning {
+                Some(n) => (n, config.buffer_size),
+                None => return,
+            }
+        };
+        // txs_cache reads the bodies of the recent blocks
+        let keep = cmp::max(keep, TXS_HISTORY + bs + 1);
+        let (height, _) = self.get_status();
+        let (finalized, _) = self.finalized();
+        let body_bound = height.saturating_sub(keep);
+        let fork_bound = finalized;
+
+        let mut batch = self.db.transaction();
+        let mut pruned = 0;
+        {
+            let mut block_headers = self.block_headers.write();
+            let mut block_bodies = self.block_bodies.write();
+            let mut block_receipts = self.block_receipts.write();
+            let prefix = [ExtrasIndex::BlockAtHeight as u8];
+            for (key, _) in self.db.iter_from_prefix(db::COL_EXTRA, &prefix) {
+                let (h, hash) = match decode_block_at_height_key(&key) {
+                    Some(v) => v,
+                    None => break,
+                };
+                if h >= fork_bound && h >= body_bound {
+                    break;
+                }
+                if self.block_hash_by_number(h) == Some(hash) {
+                    if h >= body_bound {
+                        continue;
+                    }
+                } else {
+                    if h >= fork_bound {
+                        continue;
+                    }
+                    batch.delete_with_cache(db::COL_HEADERS, &mut *block_headers, hash);
+                }
+                batch.delete_with_cache(db::COL_BODIES, &mut *block_bodies, hash);
+                batch.delete_with_cache(db::COL_RECEIPTS, &mut *block_receipts, hash);
+                batch.delete(db::COL_EXTRA, &key);
+                pruned += 1;
+            }
+        }
+        self.db.write(batch).expect("DB write failed.");
+        if pruned > 0 {
+            info!("pruned {} blocks below {}", pruned, cmp::min(body_bound, fork_bound));
+        }
+    }
+
     pub fn collect_garbage(&self) {
         let current_size = self.cache_size().total();
 
@@ -885,6 +1328,7 @@ impl Chain {
         let mut block_bodies = self.block_bodies.write();
         let mut block_hashes = self.block_hashes.write();
         let mut transaction_addresses = self.transaction_addresses.write();
+        let mut block_receipts = self.block_receipts.write();
 
         let mut cache_man = self.cache_man.lock();
         cache_man.collect_garbage(current_size, | ids | {
@@ -894,6 +1338,7 @@ impl Chain {
                     CacheId::BlockBody(ref h) => { block_bodies.remove(h); },
                     CacheId::BlockHashes(ref h) => { block_hashes.remove(h); }
                     CacheId::TransactionAddresses(ref h) => { transaction_addresses.remove(h); }
+                    CacheId::BlockReceipts(ref h) => { block_receipts.remove(h); }
                 }
             }
 
@@ -901,12 +1346,321 @@ impl Chain {
             block_bodies.shrink_to_fit();
             block_hashes.shrink_to_fit();
             transaction_addresses.shrink_to_fit();
+            block_receipts.shrink_to_fit();
 
             block_headers.heap_size_of_children() +
             block_bodies.heap_size_of_children() +
             block_hashes.heap_size_of_children() +
-            transaction_addresses.heap_size_of_children()
+            transaction_addresses.heap_size_of_children() +
+            block_receipts.heap_size_of_children()
         });
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(any(test, feature = "test-helpers"))]
+#[path = "test_helpers.rs"]
+pub mod test_helpers;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvdb::in_memory;
+    use rlp;
+    use crypto::KeyPair;
+    use util::timesource::MockTime;
+    use transaction::Transaction;
+    use state::StateOp;
+    use validators::{ValidatorOp, VALIDATOR_KEY, PROOF_KEY_LEN};
+    use super::test_helpers::{SIGNER, NOW, new_config, new_chain, valid_block};
+
+    fn tx(private_key: &H256, key: &[u8], value: &[u8], timestamp: u64) -> SignedTransaction {
+        let mut tx = Transaction::new(timestamp);
+        tx.set_data(rlp::encode(&StateOp { key: key.to_vec(), value: value.to_vec() }).to_vec());
+        tx.sign(private_key)
+    }
+
+    fn validator_tx(private_key: &H256, op: &ValidatorOp, timestamp: u64) -> SignedTransaction {
+        tx(private_key, VALIDATOR_KEY, &rlp::encode(op), timestamp)
+    }
+
+    #[test]
+    fn insert_checks_state_root() {
+        let chain = new_chain("");
+        let (_, genesis) = chain.get_status();
+        let block = valid_block(&chain, genesis, vec![tx(&H256::from(SIGNER), b"foo", b"bar", 1000)]);
+
+        let mut tampered = block.clone();
+        tampered.state_root = H256::from(1);
+        tampered.hash.set(None);
+        tampered.sign(&H256::from(SIGNER));
+        assert_eq!(chain.insert(tampered), Err(Error::InvalidStateRoot));
+        assert_eq!(chain.get_status(), (0, genesis));
+
+        assert_eq!(chain.insert(block.clone()), Ok(()));
+        assert_eq!(chain.get_status(), (1, block.hash()));
+    }
+
+    #[test]
+    fn receipts_of_inserted_block() {
+        let chain = new_chain("");
+        let (_, genesis) = chain.get_status();
+        let outsider = H256::from(7);
+        let retire = ValidatorOp::Retire(*KeyPair::from_privkey(outsider).unwrap().pubkey());
+        let txs = vec![tx(&H256::from(SIGNER), b"foo", b"bar", 1000), validator_tx(&outsider, &retire, 1001)];
+        let block = valid_block(&chain, genesis, txs.clone());
+
+        let mut tampered = block.clone();
+        tampered.receipts_root = H256::from(1);
+        tampered.hash.set(None);
+        tampered.sign(&H256::from(SIGNER));
+        assert_eq!(chain.insert(tampered), Err(Error::InvalidReceiptsRoot));
+        assert_eq!(chain.get_transaction_address(&txs[0].hash()), None);
+
+        assert_eq!(chain.insert(block.clone()), Ok(()));
+        for (i, tx) in txs.iter().enumerate() {
+            let address = chain.get_transaction_address(&tx.hash()).unwrap();
+            assert_eq!((address.block_hash, address.index), (block.hash(), i));
+            let receipt = chain.get_transaction_receipt(&tx.hash()).unwrap();
+            assert_eq!((receipt.transaction_hash, receipt.block_height, receipt.index), (tx.hash(), 1, i));
+        }
+        // a transaction which fails is included all the same
+        assert!(chain.get_transaction_receipt(&txs[0].hash()).unwrap().success);
+        assert!(!chain.get_transaction_receipt(&txs[1].hash()).unwrap().success);
+    }
+
+    #[test]
+    fn state_follows_long_fork() {
+        // deep enough a fork is not final yet
+        let chain = new_chain("confirmations = 20");
+        let key = H256::from(SIGNER);
+        let address = KeyPair::from_privkey(key).unwrap().address();
+        let (_, genesis) = chain.get_status();
+
+        let mut a = genesis;
+        for i in 0..7 {
+            let txs = match i {
+                0 => vec![tx(&key, b"foo", b"a", 100000), tx(&key, b"a", b"a", 100001)],
+                _ => Vec::new(),
+            };
+            a = chain.gen_block(i, a, 10 * (i + 1), vec![i as u8], txs).unwrap().hash();
+        }
+        assert_eq!(chain.get_status(), (7, a));
+
+        // the fork branches off further back than transactions are checked
+        let mut b = genesis;
+        for i in 0..8 {
+            let txs = match i {
+                0 => vec![tx(&key, b"foo", b"b", 100002)],
+                _ => Vec::new(),
+            };
+            let block = valid_block(&chain, b, txs);
+            b = block.hash();
+            assert_eq!(chain.insert(block), Ok(()));
+        }
+        assert_eq!(chain.get_status(), (8, b));
+        assert!(chain.get_block_header_by_hash(&b).unwrap().verified);
+
+        let state = chain.best_state().unwrap();
+        assert_eq!(state.get(&address, b"foo").unwrap(), Some(b"b".to_vec()));
+        assert_eq!(state.get(&address, b"a").unwrap(), None);
+        // the state of the retracted blocks stays available
+        let state = chain.state_at(&a).unwrap();
+        assert_eq!(state.get(&address, b"foo").unwrap(), Some(b"a".to_vec()));
+        assert_eq!(state.get(&address, b"a").unwrap(), Some(b"a".to_vec()));
+    }
+
+    #[test]
+    fn events_of_reorg() {
+        let chain = new_chain("fork_choice = \"earliest_timestamp\"");
+        let key = H256::from(SIGNER);
+        let (_, genesis) = chain.get_status();
+        let kept = tx(&key, b"foo", b"1", 1000);
+        let dropped = tx(&key, b"foo", b"2", 1001);
+        let events = chain.subscribe();
+
+        let a1 = chain.gen_block(0, genesis, 10, vec![1], vec![kept.clone(), dropped.clone()]).unwrap();
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![
+            ChainEvent::TxIncluded { hash: kept.hash(), block_hash: a1.hash(), index: 0 },
+            ChainEvent::TxIncluded { hash: dropped.hash(), block_hash: a1.hash(), index: 1 },
+            ChainEvent::NewBest { height: 1, hash: a1.hash() },
+        ]);
+
+        // a later block of the same height does not win
+        let b1 = chain.gen_block(0, genesis, 11, vec![2], vec![kept.clone()]).unwrap();
+        assert_eq!(events.try_iter().count(), 0);
+        let b2 = chain.gen_block(1, b1.hash(), 20, vec![3], Vec::new()).unwrap();
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![
+            ChainEvent::Reorg { retracted: vec![a1.hash()], enacted: vec![b1.hash(), b2.hash()] },
+            ChainEvent::TxIncluded { hash: kept.hash(), block_hash: b1.hash(), index: 0 },
+            ChainEvent::NewBest { height: 2, hash: b2.hash() },
+        ]);
+        assert_eq!(chain.get_transaction_address(&kept.hash()).unwrap().block_hash, b1.hash());
+    }
+
+    #[test]
+    fn finality() {
+        let chain = new_chain("confirmations = 2");
+        let (_, genesis) = chain.get_status();
+        let included = tx(&H256::from(SIGNER), b"foo", b"bar", 1000);
+        let mut a = vec![genesis];
+        for i in 0..5 {
+            let txs = if i == 1 { vec![included.clone()] } else { Vec::new() };
+            let block = chain.gen_block(i, a[i as usize], 10 * (i + 1), vec![i as u8], txs).unwrap();
+            a.push(block.hash());
+        }
+        assert_eq!(chain.finalized(), (3, a[3]));
+        assert!(chain.is_finalized(&a[3]));
+        assert!(!chain.is_finalized(&a[4]));
+        assert_eq!(chain.block_confirmations(&a[5]), Some(1));
+        assert_eq!(chain.transaction_confirmations(&included.hash()), Some(4));
+
+        // a fork off a block below the finalized one never becomes the best chain
+        let mut hash = a[1];
+        for i in 1..6 {
+            hash = chain.gen_block(i, hash, 10 * (i + 1) + 1, vec![10 + i as u8], Vec::new()).unwrap().hash();
+        }
+        assert_eq!(chain.get_status(), (5, a[5]));
+        assert_eq!(chain.block_confirmations(&hash), None);
+
+        // a fork off the finalized block still can
+        let mut hash = a[3];
+        for i in 3..6 {
+            hash = chain.gen_block(i, hash, 10 * (i + 1) + 2, vec![20 + i as u8], Vec::new()).unwrap().hash();
+        }
+        assert_eq!(chain.get_status(), (6, hash));
+        assert_eq!(chain.transaction_confirmations(&included.hash()), Some(5));
+    }
+
+    #[test]
+    fn prune_bodies_and_stale_forks() {
+        // bodies are kept for at least the blocks of the transactions cache
+        let chain = new_chain("fork_choice = \"earliest_timestamp\"\npruning = 1\nconfirmations = 20");
+        let (_, genesis) = chain.get_status();
+        let mut a = vec![genesis];
+        for i in 0..110 {
+            let block = chain.gen_block(i, a[i as usize], 10 * (i + 1), vec![i as u8], Vec::new()).unwrap();
+            a.push(block.hash());
+        }
+        let stale = valid_block(&chain, a[1], Vec::new());
+        // above the finalized block 90, but deeper than `buffer_size`
+        let live = valid_block(&chain, a[95], Vec::new());
+        assert_eq!(chain.insert(stale.clone()), Ok(()));
+        assert_eq!(chain.insert(live.clone()), Ok(()));
+        assert_eq!(chain.get_status(), (110, a[110]));
+
+        chain.prune();
+        for (height, hash) in a.iter().enumerate() {
+            assert!(chain.get_block_header_by_hash(hash).is_some());
+            assert_eq!(chain.get_block_body_by_hash(hash).is_some(), height >= 4);
+            assert_eq!(chain.get_block_receipts(hash).is_some(), height >= 4);
+        }
+        assert_eq!(chain.get_block_header_by_hash(&stale.hash()), None);
+        assert_eq!(chain.get_block_body_by_hash(&stale.hash()), None);
+        // forks above the finalized block may still win
+        assert!(chain.get_block_body_by_hash(&live.hash()).is_some());
+    }
+
+    #[test]
+    fn validators_change_at_epoch_boundary() {
+        let chain = new_chain("");
+        let signer = *KeyPair::from_privkey(H256::from(SIGNER)).unwrap().pubkey();
+        let joining_key = H256::from(7);
+        let joining = *KeyPair::from_privkey(joining_key).unwrap().pubkey();
+        let keys = (vec![3; PROOF_KEY_LEN], vec![4; PROOF_KEY_LEN]);
+        let register = validator_tx(&joining_key, &ValidatorOp::register(keys.0.clone(), keys.1.clone(), joining, &H256::from(SIGNER)), 1000);
+        // the only validator tries to push out the one joining
+        let attack = validator_tx(&H256::from(SIGNER), &ValidatorOp::Retire(joining), 1001);
+
+        let (_, genesis) = chain.get_status();
+        let b1 = chain.gen_block(0, genesis, 10, vec![1], vec![register.clone()]).unwrap();
+        let b2 = chain.gen_block(1, b1.hash(), 20, vec![2], vec![attack.clone()]).unwrap();
+        let b3 = chain.gen_block(2, b2.hash(), 30, vec![3], Vec::new()).unwrap();
+        assert!(chain.get_transaction_receipt(&register.hash()).unwrap().success);
+        assert!(!chain.get_transaction_receipt(&attack.hash()).unwrap().success);
+
+        // the blocks of the first epoch are checked against the initial set
+        assert_eq!(chain.validators(3, b2.hash()).unwrap().keys().collect::<Vec<_>>(), vec![&signer]);
+        // the registration takes effect at the boundary
+        let set = chain.validators(4, b3.hash()).unwrap();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set[&joining], keys);
+    }
+
+    #[test]
+    fn equivocation_evidence() {
+        let chain = new_chain("");
+        let signer = *KeyPair::from_privkey(H256::from(SIGNER)).unwrap().pubkey();
+        let events = chain.subscribe();
+        let (_, genesis) = chain.get_status();
+        let b1 = chain.gen_block(0, genesis, 10, vec![1], Vec::new()).unwrap();
+        let b2 = chain.gen_block(1, b1.hash(), 20, vec![2], Vec::new()).unwrap();
+        // another block for the slot of b2
+        let other = chain.gen_block(1, b1.hash(), 20, vec![3], Vec::new()).unwrap();
+        assert_eq!(chain.find_equivocation(&b1), None);
+        let evidence = chain.find_equivocation(&other).unwrap();
+        assert_eq!(evidence, Evidence::new(b2.header.clone(), other.header.clone()));
+        assert_eq!(chain.find_equivocation(&b2), Some(evidence.clone()));
+
+        assert_eq!(chain.evidence(&signer), None);
+        assert_eq!(chain.add_evidence(evidence.clone()), Ok(true));
+        assert_eq!(chain.add_evidence(evidence.clone()), Ok(false));
+        assert_eq!(chain.evidence(&signer), Some(evidence.clone()));
+        assert!(events.try_iter().any(|e| e == ChainEvent::Equivocation(evidence.clone())));
+
+        // the evidence only records the offender
+        assert!(chain.validators(3, b2.hash()).unwrap().contains_key(&signer));
+
+        // evidence against a key which is no validator
+        let mut a = Block::init(1, 10, genesis, Vec::new(), vec![1]);
+        let mut b = Block::init(1, 10, genesis, Vec::new(), vec![2]);
+        a.sign(&H256::from(7));
+        b.sign(&H256::from(7));
+        assert_eq!(chain.add_evidence(Evidence::new(a.header, b.header)), Err(Error::NotValidator));
+        // nor evidence at all
+        assert_eq!(chain.add_evidence(Evidence::new(b1.header.clone(), b2.header.clone())), Err(Error::InvalidEvidence));
+    }
+
+    #[test]
+    fn refuse_other_databases() {
+        let config = new_config("", Arc::new(MockTime::new(NOW)));
+        let db: Arc<KeyValueDB> = Arc::new(in_memory(db::NUM_COLUMNS.unwrap()));
+        Chain::init(config.clone(), db.clone()).unwrap();
+        assert!(Chain::init(config.clone(), db.clone()).is_ok());
+
+        config.write().start_time = 2;
+        assert_eq!(Chain::init(config.clone(), db.clone()).err(), Some(Error::GenesisMismatch));
+        config.write().start_time = 1;
+
+        // written by a version which hashed headers with their signature
+        let mut batch = db.transaction();
+        batch.delete(db::COL_EXTRA, b"genesis_hash");
+        db.write(batch).unwrap();
+        assert_eq!(Chain::init(config, db).err(), Some(Error::OutdatedDatabase));
+    }
+
+    #[test]
+    fn unknown_fork_choice() {
+        let config = new_config("fork_choice = \"longest\"", Arc::new(MockTime::new(NOW)));
+        let db = Arc::new(in_memory(db::NUM_COLUMNS.unwrap()));
+        assert_eq!(Chain::init(config, db).err(), Some(Error::UnknownForkChoice("longest".to_owned())));
+    }
+
+    #[test]
+    fn drop_invalid_future_block() {
+        let time = Arc::new(MockTime::new(NOW));
+        let config = new_config("", time.clone());
+        let chain = Chain::init(config.clone(), Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap();
+        let (_, genesis) = chain.get_status();
+        let now = config.read().now().unwrap();
+        // the proof is only checked once the block is due
+        let mut block = Block::init(1, now + 1, genesis, Vec::new(), Vec::new());
+        block.sign(&H256::from(SIGNER));
+        assert_eq!(chain.insert(block), Err(Error::FutureBlock));
+
+        time.advance(1000000000);
+        chain.handle_pending();
+        assert!(chain.future_blocks.read().is_empty());
+        assert_eq!(chain.get_status(), (0, genesis));
+    }
+}
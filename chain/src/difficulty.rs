@@ -0,0 +1,84 @@
+//! Proof target of an epoch.
+//!
+//! Every validator tries one proof per tick, so for one block every `steps`
+//! seconds the target is `U256::max_value() / (validators * steps * nps)`,
+//! with the validators active in the epoch. With adjustment enabled, the
+//! target is instead the one of the epoch before, rescaled to the new number
+//! of validators and by how much slower or faster than planned the blocks of
+//! the epoch before came, by a factor of at most `MAX_ADJUSTMENT`. All of it
+//! is read from the fork of the block, so every node gets the same target.
+
+use std::cmp;
+use util::U256;
+
+/// Bound of the change of the target from one epoch to the next.
+pub const MAX_ADJUSTMENT: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Epoch {
+    /// Blocks of the epoch have a proof below this.
+    pub target: U256,
+    pub validators: usize,
+    /// Timestamp of the last block of the epoch before.
+    pub timestamp: u64,
+}
+
+impl Epoch {
+    /// An epoch with the target of its number of validators.
+    pub fn new(validators: usize, timestamp: u64, steps: u64, nps: u64) -> Self {
+        let validators = cmp::max(validators, 1) as u64;
+        Epoch {
+            target: U256::max_value() / U256::from(validators * steps * nps),
+            validators: validators as usize,
+            timestamp: timestamp,
+        }
+    }
+
+    /// The epoch after this one, which took `blocks` blocks up to `timestamp`.
+    pub fn next(&self, validators: usize, timestamp: u64, blocks: u64, steps: u64, nps: u64) -> Self {
+        let validators = cmp::max(validators, 1) as u64;
+        let expected = blocks * steps * nps;
+        let observed = timestamp.saturating_sub(self.timestamp);
+        let observed = cmp::min(cmp::max(observed, expected / MAX_ADJUSTMENT), expected * MAX_ADJUSTMENT);
+
+        let target = (self.target / U256::from(validators * expected))
+            .saturating_mul(U256::from(self.validators as u64 * observed));
+        Epoch {
+            target: cmp::max(target, U256::one()),
+            validators: validators as usize,
+            timestamp: timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_of_validators() {
+        let one = Epoch::new(1, 0, 6, 10);
+        let three = Epoch::new(3, 0, 6, 10);
+        assert_eq!(one.target, U256::max_value() / U256::from(60));
+        assert_eq!(three.target, one.target / U256::from(3));
+        assert_eq!(Epoch::new(0, 0, 6, 10), one);
+    }
+
+    #[test]
+    fn adjust_to_intervals() {
+        let epoch = Epoch::new(2, 100, 6, 10);
+        // 10 blocks as planned
+        let same = epoch.next(2, 700, 10, 6, 10);
+        assert!(same.target <= epoch.target && same.target > epoch.target - epoch.target / U256::from(1000));
+        // twice as slow, the target doubles
+        let slow = epoch.next(2, 1300, 10, 6, 10);
+        assert!(slow.target > same.target + same.target / U256::from(2));
+        // far too fast, the target halves at most
+        let fast = epoch.next(2, 101, 10, 6, 10);
+        assert!(fast.target >= epoch.target / U256::from(3));
+        assert!(fast.target < same.target);
+        // a validator joined at the planned pace
+        let joined = epoch.next(3, 700, 10, 6, 10);
+        assert!(joined.target < same.target);
+    }
+}
@@ -16,5 +16,24 @@ pub enum Error {
     InvalidFormat,
     LongFork,
     UnknownAncestor,
+    UnknownState,
     NTPError,
+    /// Blocks of the best chain are missing from the database.
+    InconsistentDatabase,
+    /// The database was initialized with another genesis block.
+    GenesisMismatch,
+    /// The database was written by a version with another header hash.
+    OutdatedDatabase,
+    /// The sender of a validator transaction is not a validator.
+    NotValidator,
+    /// The key of a validator transaction is registered already.
+    DuplicateValidator,
+    /// A registration is not approved by a validator of the set.
+    UnauthorizedValidator,
+    /// The only validator left cannot retire.
+    LastValidator,
+    /// The headers of an evidence are not signed by one key for one slot.
+    InvalidEvidence,
+    /// The configured `fork_choice` names no rule.
+    UnknownForkChoice(String),
 }
\ No newline at end of file
@@ -0,0 +1,28 @@
+//! Notifications of changes to the best chain and of misbehaving validators.
+
+use util::hash::H256;
+use block::BlockNumber;
+use evidence::Evidence;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    /// The best block changed.
+    NewBest {
+        height: BlockNumber,
+        hash: H256,
+    },
+    /// Blocks left the best chain for a fork, both lists ordered by height.
+    /// Their transactions are pending again unless `enacted` includes them.
+    Reorg {
+        retracted: Vec<H256>,
+        enacted: Vec<H256>,
+    },
+    /// A transaction was included by a block which joined the best chain.
+    TxIncluded {
+        hash: H256,
+        block_hash: H256,
+        index: usize,
+    },
+    /// New evidence of a validator signing two blocks for one slot.
+    Equivocation(Evidence),
+}
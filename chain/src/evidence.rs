@@ -0,0 +1,79 @@
+//! Evidence of a validator signing two blocks for one slot.
+//!
+//! A proof only depends on the timestamp, the height and the ancestor, so a
+//! validator whose proof is good enough for a slot can sign any number of
+//! different blocks with it. Two headers of the same height and timestamp
+//! signed by the same key are the evidence. It is stored by the key of the
+//! offender and gossiped to the peers.
+
+use util::hash::H256;
+use util::H512;
+use block::Header;
+use error::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, RlpEncodable, RlpDecodable)]
+pub struct Evidence {
+    pub first: Header,
+    pub second: Header,
+}
+
+impl Evidence {
+    /// The headers are ordered by hash, so every node records the same evidence.
+    pub fn new(a: Header, b: Header) -> Self {
+        if a.hash() <= b.hash() {
+            Evidence { first: a, second: b }
+        } else {
+            Evidence { first: b, second: a }
+        }
+    }
+
+    /// The key which signed both headers, if they conflict.
+    pub fn offender(&self) -> Result<H512, Error> {
+        let (a, b) = (&self.first, &self.second);
+        if a.height == 0 || a.height != b.height || a.timestamp != b.timestamp || a.hash() == b.hash() {
+            return Err(Error::InvalidEvidence);
+        }
+        let signer = a.sign_public()?;
+        if b.sign_public()? != signer {
+            return Err(Error::InvalidEvidence);
+        }
+        Ok(signer)
+    }
+
+    pub fn hashes(&self) -> (H256, H256) {
+        (self.first.hash(), self.second.hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlp;
+    use block::Block;
+    use crypto::KeyPair;
+
+    fn signed(height: u64, timestamp: u64, parent: u64, private_key: &H256) -> Header {
+        let mut block = Block::init(height, timestamp, H256::from(parent), Vec::new(), vec![1]);
+        block.sign(private_key);
+        block.header
+    }
+
+    #[test]
+    fn conflicting_headers() {
+        let key = H256::from("40f2d8f8e1594579824fd04edfc7ff1ddffd6be153b23f4318e1acff037d3ea9");
+        let public = *KeyPair::from_privkey(key).unwrap().pubkey();
+        let a = signed(5, 100, 1, &key);
+        let b = signed(5, 100, 2, &key);
+
+        let evidence = Evidence::new(b.clone(), a.clone());
+        assert_eq!(evidence, Evidence::new(a.clone(), b.clone()));
+        assert_eq!(evidence.offender(), Ok(public));
+        assert_eq!(rlp::decode::<Evidence>(&rlp::encode(&evidence)), evidence);
+
+        assert_eq!(Evidence::new(a.clone(), a.clone()).offender(), Err(Error::InvalidEvidence));
+        let later = signed(5, 101, 2, &key);
+        assert_eq!(Evidence::new(a.clone(), later).offender(), Err(Error::InvalidEvidence));
+        let other = signed(5, 100, 2, &H256::from(7));
+        assert_eq!(Evidence::new(a, other).offender(), Err(Error::InvalidEvidence));
+    }
+}
@@ -0,0 +1,169 @@
+//! Portable chain file for backups and seeding nodes.
+//!
+//! The file is the canonical blocks in height order, each one an rlp list of
+//! its header and its body, with nothing in between.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use rlp::{self, PayloadInfo, UntrustedRlp};
+use block::Block;
+use chain::Chain;
+use error::Error;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    /// The block at this height was rejected by the chain.
+    Block(u64, Error),
+}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        ImportError::Io(err)
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportError::Io(ref err) => write!(f, "read error {}", err),
+            ImportError::Block(height, ref err) => write!(f, "invalid block {}: {:?}", height, err),
+        }
+    }
+}
+
+pub fn write_block<W: Write>(w: &mut W, block: &Block) -> io::Result<()> {
+    w.write_all(&rlp::encode(block))
+}
+
+/// Read the next block, `None` at the end of the file.
+pub fn read_block<R: Read>(r: &mut R) -> io::Result<Option<Block>> {
+    let mut first = [0u8; 1];
+    if r.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+    if first[0] < 0xc0 {
+        return Err(invalid_data("not a block"));
+    }
+    // the length of a list longer than 55 bytes follows the prefix
+    let mut header = first.to_vec();
+    if first[0] > 0xf7 {
+        let mut len = vec![0; (first[0] - 0xf7) as usize];
+        r.read_exact(&mut len)?;
+        header.extend_from_slice(&len);
+    }
+
+    let info = PayloadInfo::from(&header).map_err(|e| invalid_data(&format!("{:?}", e)))?;
+    let mut bytes = header;
+    bytes.resize(info.total(), 0);
+    r.read_exact(&mut bytes[info.header_len..])?;
+    UntrustedRlp::new(&bytes).as_val().map(Some).map_err(|e| invalid_data(&format!("{:?}", e)))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// Write the canonical blocks from height `from` to `to`, both included.
+/// Returns the number of blocks written.
+pub fn export<W: Write>(chain: &Chain, from: u64, to: u64, w: &mut W) -> io::Result<u64> {
+    let mut n = 0;
+    for height in from..(to + 1) {
+        let block = chain.block_hash_by_number(height)
+            .and_then(|hash| chain.get_block_by_hash(&hash))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("block {} not found", height)))?;
+        write_block(w, &block)?;
+        n += 1;
+    }
+    w.flush()?;
+    Ok(n)
+}
+
+/// Insert every block of the file into `chain`, skipping the known ones.
+/// Returns the number of blocks inserted.
+pub fn import<R: Read>(chain: &Chain, r: &mut R) -> Result<u64, ImportError> {
+    let mut n = 0;
+    while let Some(block) = read_block(r)? {
+        let height = block.height;
+        match chain.insert(block) {
+            Ok(_) => n += 1,
+            Err(Error::DuplicateBlock) => {}
+            Err(err) => return Err(ImportError::Block(height, err)),
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use kvdb::in_memory;
+    use transaction::Transaction;
+    use util::hash::H256;
+    use util::timesource::MockTime;
+    use db;
+    use chain::test_helpers::{NOW, new_config, mine};
+
+    #[test]
+    fn write_and_read() {
+        let txs = (0..100).map(|i| Transaction::new(i).sign(&H256::from(1))).collect();
+        let blocks = vec![
+            Block::init(1, 10, H256::from(1), Vec::new(), vec![1]),
+            Block::init(2, 20, H256::from(2), txs, vec![2; 21]),
+        ];
+        let mut file = Vec::new();
+        for block in &blocks {
+            write_block(&mut file, block).unwrap();
+        }
+
+        let mut r = Cursor::new(file.clone());
+        assert_eq!(read_block(&mut r).unwrap(), Some(blocks[0].clone()));
+        assert_eq!(read_block(&mut r).unwrap(), Some(blocks[1].clone()));
+        assert_eq!(read_block(&mut r).unwrap(), None);
+
+        file.truncate(file.len() - 1);
+        let mut r = Cursor::new(file);
+        read_block(&mut r).unwrap();
+        assert!(read_block(&mut r).is_err());
+
+        assert!(read_block(&mut Cursor::new(vec![0x80])).is_err());
+    }
+
+    #[test]
+    fn export_and_import() {
+        let config = new_config("", Arc::new(MockTime::new(NOW)));
+        let remote = Chain::init(config.clone(), Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap();
+        for _ in 0..3 {
+            mine(&remote);
+        }
+        let mut file = Vec::new();
+        assert_eq!(export(&remote, 1, 3, &mut file).unwrap(), 3);
+
+        let local = Chain::init(config.clone(), Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap();
+        assert_eq!(import(&local, &mut Cursor::new(file.clone())).unwrap(), 3);
+        assert_eq!(local.get_status(), remote.get_status());
+        // known blocks are skipped
+        assert_eq!(import(&local, &mut Cursor::new(file)).unwrap(), 0);
+    }
+
+    #[test]
+    fn import_tampered_block() {
+        let config = new_config("", Arc::new(MockTime::new(NOW)));
+        let remote = Chain::init(config.clone(), Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap();
+        let first = mine(&remote);
+        let mut second = mine(&remote);
+        second.timestamp += 1;
+        let mut file = Vec::new();
+        write_block(&mut file, &first).unwrap();
+        write_block(&mut file, &second).unwrap();
+
+        let local = Chain::init(config, Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap();
+        match import(&local, &mut Cursor::new(file)) {
+            Err(ImportError::Block(2, _)) => {}
+            other => panic!("tampered block imported: {:?}", other),
+        }
+        assert_eq!(local.get_status(), (1, first.hash()));
+    }
+}
@@ -20,9 +20,10 @@ use std::ops;
 // use std::io::Write;
 use db::Key;
 use block::{BlockNumber, RichHeader, Header, Body};
+use receipt::BlockReceipts;
 
 use heapsize::HeapSizeOf;
-use bigint::hash::{H256, H264};
+use bigint::hash::{H256, H264, H512};
 // use kvdb::PREFIX_LEN as DB_PREFIX_LEN;
 
 /// Represents index of extra data in database
@@ -32,6 +33,10 @@ pub enum ExtrasIndex {
     BlockHash = 1,
     /// Transaction address index
     TransactionAddress = 2,
+    /// Index of the stored blocks by height, for pruning and equivocation checks
+    BlockAtHeight = 3,
+    /// Equivocation evidence by the key of the offender
+    Evidence = 4,
 }
 
 fn with_index(hash: &H256, i: ExtrasIndex) -> H264 {
@@ -65,6 +70,34 @@ impl Key<H256> for BlockNumber {
     }
 }
 
+/// Key of block `hash` in the index of stored blocks, which orders by height.
+pub fn block_at_height_key(height: BlockNumber, hash: &H256) -> [u8; 41] {
+    let mut result = [0u8; 41];
+    result[0] = ExtrasIndex::BlockAtHeight as u8;
+    for i in 0..8 {
+        result[1 + i] = (height >> (56 - 8 * i)) as u8;
+    }
+    result[9..].clone_from_slice(hash);
+    result
+}
+
+/// Height and hash in a key of the index of stored blocks.
+pub fn decode_block_at_height_key(key: &[u8]) -> Option<(BlockNumber, H256)> {
+    if key.len() != 41 || key[0] != ExtrasIndex::BlockAtHeight as u8 {
+        return None;
+    }
+    let height = key[1..9].iter().fold(0, |h, b| (h << 8) | *b as u64);
+    Some((height, H256::from_slice(&key[9..])))
+}
+
+/// Key of the equivocation evidence against `offender`.
+pub fn evidence_key(offender: &H512) -> [u8; 65] {
+    let mut result = [0u8; 65];
+    result[0] = ExtrasIndex::Evidence as u8;
+    result[1..].clone_from_slice(offender);
+    result
+}
+
 impl Key<Header> for H256 {
     type Target = H256;
 
@@ -89,6 +122,14 @@ impl Key<Body> for H256 {
     }
 }
 
+impl Key<BlockReceipts> for H256 {
+    type Target = H256;
+
+    fn key(&self) -> H256 {
+        *self
+    }
+}
+
 impl Key<TransactionAddress> for H256 {
     type Target = H264;
 
@@ -109,3 +150,18 @@ pub struct TransactionAddress {
 impl HeapSizeOf for TransactionAddress {
     fn heap_size_of_children(&self) -> usize { 0 }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_at_height_key_order() {
+        let a = block_at_height_key(255, &H256::from(2));
+        let b = block_at_height_key(256, &H256::from(1));
+        assert!(a[..] < b[..]);
+        assert_eq!(decode_block_at_height_key(&a), Some((255, H256::from(2))));
+        assert_eq!(decode_block_at_height_key(&b), Some((256, H256::from(1))));
+        assert_eq!(decode_block_at_height_key(&a[..40]), None);
+    }
+}
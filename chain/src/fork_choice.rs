@@ -0,0 +1,103 @@
+//! Fork choice: which of two blocks is the better head of the chain.
+//!
+//! A higher block always wins. At the same height the configured rule
+//! decides, and the lower block hash breaks ties, so every node picks the
+//! same head out of the same blocks whatever order they arrive in.
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+use block::Header;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ForkChoice {
+    /// The block with the lower proof difficulty value, i.e. the better proof.
+    LowestDifficulty,
+    /// The block with the earlier timestamp.
+    EarliestTimestamp,
+}
+
+impl Default for ForkChoice {
+    fn default() -> Self {
+        ForkChoice::LowestDifficulty
+    }
+}
+
+impl FromStr for ForkChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lowest_difficulty" => Ok(ForkChoice::LowestDifficulty),
+            "earliest_timestamp" => Ok(ForkChoice::EarliestTimestamp),
+            _ => Err(format!("unknown fork choice {}", s)),
+        }
+    }
+}
+
+impl ForkChoice {
+    /// Order two heads, the better one is `Greater`.
+    pub fn compare(&self, a: &Header, b: &Header) -> Ordering {
+        a.height.cmp(&b.height)
+            .then_with(|| match *self {
+                ForkChoice::LowestDifficulty => b.difficulty().cmp(&a.difficulty()),
+                ForkChoice::EarliestTimestamp => b.timestamp.cmp(&a.timestamp),
+            })
+            .then_with(|| b.hash().cmp(&a.hash()))
+    }
+
+    /// Whether `candidate` should replace `current` as head.
+    pub fn prefer(&self, candidate: &Header, current: &Header) -> bool {
+        self.compare(candidate, current) == Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use block::Block;
+    use util::hash::H256;
+
+    fn header(height: u64, timestamp: u64, proof: Vec<u8>) -> Header {
+        Block::init(height, timestamp, H256::default(), Vec::new(), proof).header
+    }
+
+    #[test]
+    fn higher_wins() {
+        let a = header(2, 10, vec![1]);
+        let b = header(1, 5, vec![2]);
+        for rule in &[ForkChoice::LowestDifficulty, ForkChoice::EarliestTimestamp] {
+            assert!(rule.prefer(&a, &b));
+            assert!(!rule.prefer(&b, &a));
+        }
+    }
+
+    #[test]
+    fn same_height() {
+        let a = header(1, 10, vec![1]);
+        let b = header(1, 20, vec![2]);
+        let rule = ForkChoice::EarliestTimestamp;
+        assert!(rule.prefer(&a, &b));
+        assert!(!rule.prefer(&b, &a));
+
+        let rule = ForkChoice::LowestDifficulty;
+        assert_eq!(rule.prefer(&a, &b), a.difficulty() < b.difficulty());
+        assert_eq!(rule.prefer(&a, &b), !rule.prefer(&b, &a));
+    }
+
+    #[test]
+    fn deterministic_tie_break() {
+        let a = header(1, 10, vec![1]);
+        let mut b = header(1, 10, vec![1]);
+        b.state_root = H256::from(1);
+        let rule = ForkChoice::EarliestTimestamp;
+        assert!(rule.prefer(&a, &b) != rule.prefer(&b, &a));
+        assert!(!rule.prefer(&a, &a.clone()));
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!("lowest_difficulty".parse::<ForkChoice>(), Ok(ForkChoice::LowestDifficulty));
+        assert_eq!("EARLIEST_TIMESTAMP".parse::<ForkChoice>(), Ok(ForkChoice::EarliestTimestamp));
+        assert!("random".parse::<ForkChoice>().is_err());
+    }
+}
@@ -0,0 +1,255 @@
+//! Consistency check of the best chain stored in the database.
+//!
+//! Walks from the best block back to the genesis and checks the parent
+//! links, the number index, the transaction addresses, and the signature and
+//! proof of every block. Repairing rewrites the indexes and moves the best
+//! block below the first block which is missing or invalid, so that
+//! `Chain::init` can rebuild its caches again.
+
+use std::sync::Arc;
+use kvdb::{DBTransaction, KeyValueDB};
+use util::hash::H256;
+use util::Hashable;
+use util::config::SleepyConfig;
+use block::{Body, RichHeader, BlockNumber};
+use chain::anc_height;
+use db::{self, Key, Readable, Writable};
+use extras::{ExtrasIndex, TransactionAddress};
+use validators;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// No header is stored for a block of the best chain.
+    MissingHeader(H256),
+    /// The stored header hashes to something else.
+    HashMismatch(BlockNumber, H256),
+    /// The parent of the block is not one block lower.
+    BrokenLink(BlockNumber, H256),
+    /// The number index points to another block or nowhere.
+    NumberIndex(BlockNumber, H256),
+    MissingBody(BlockNumber, H256),
+    InvalidTransactionsRoot(BlockNumber, H256),
+    /// A transaction of the block has another address or none.
+    TransactionAddress(BlockNumber, H256),
+    InvalidSignature(BlockNumber, H256),
+    InvalidProof(BlockNumber, H256),
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Blocks of the best chain checked.
+    pub checked: u64,
+    pub issues: Vec<Issue>,
+    /// The best block, if it has to move below a broken or invalid block.
+    pub new_best: Option<(BlockNumber, H256)>,
+    pub repaired: bool,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Check the best chain in `db`, and fix what is found if `repair` is set.
+pub fn check(db: Arc<KeyValueDB>, config: &SleepyConfig, repair: bool) -> Report {
+    let mut report = Report::default();
+    let best = match db.get(db::COL_EXTRA, b"current_hash").expect("db get failed") {
+        Some(hash) => H256::from_slice(&hash),
+        None => return report,
+    };
+
+    let (mut headers, complete) = walk(&*db, best, &mut report.issues);
+    let old_height = match headers.first() {
+        Some(header) => Some(header.height),
+        None => highest_indexed(&*db),
+    };
+    if !complete {
+        let below = headers.last().map(|h| h.height).or(old_height.map(|h| h + 1)).unwrap_or(0);
+        headers = match recover(&*db, below) {
+            Some(headers) => headers,
+            None => {
+                warn!("no block of the best chain leads to the genesis");
+                return report;
+            }
+        };
+    }
+    headers.reverse();
+
+    let mut batch = DBTransaction::new();
+    let mut first_invalid = None;
+    for header in &headers {
+        let height = header.height;
+        let hash = header.hash();
+        report.checked += 1;
+
+        let indexed: Option<H256> = db.read(db::COL_EXTRA, &height);
+        if indexed != Some(hash) {
+            report.issues.push(Issue::NumberIndex(height, hash));
+            batch.write(db::COL_EXTRA, &height, &hash);
+        }
+
+        let body: Option<Body> = db.read(db::COL_BODIES, &hash);
+        match body {
+            Some(body) => {
+                if body.transactions_root() != header.transactions_root {
+                    report.issues.push(Issue::InvalidTransactionsRoot(height, hash));
+                    first_invalid = first_invalid.or(Some(height));
+                }
+                for (i, tx) in body.transactions.iter().enumerate() {
+                    let expected = TransactionAddress { block_hash: hash, index: i };
+                    let address: Option<TransactionAddress> = db.read(db::COL_EXTRA, &tx.hash());
+                    if address.as_ref() != Some(&expected) {
+                        report.issues.push(Issue::TransactionAddress(height, tx.hash()));
+                        batch.write(db::COL_EXTRA, &tx.hash(), &expected);
+                    }
+                }
+            }
+            None => {
+                // bodies of old blocks are gone in pruning mode
+                if config.pruning.is_none() {
+                    report.issues.push(Issue::MissingBody(height, hash));
+                    first_invalid = first_invalid.or(Some(height));
+                }
+            }
+        }
+
+        if height == 0 {
+            continue;
+        }
+        let set_root = headers[validators::set_height(height, config.epoch_len) as usize].state_root;
+        let keys = match validators::at(db.clone(), set_root, &config.public_keys) {
+            Ok(set) => header.sign_public().ok().and_then(|public| set.get(&public).cloned()),
+            Err(_) => None,
+        };
+        match keys {
+            Some((proof_pub, proof_g)) => {
+                let anc = &headers[anc_height(height, config.epoch_len) as usize];
+                if !header.verify_proof(anc.proof.time_signature.sha3(), proof_pub, proof_g) {
+                    report.issues.push(Issue::InvalidProof(height, hash));
+                    first_invalid = first_invalid.or(Some(height));
+                }
+            }
+            None => {
+                report.issues.push(Issue::InvalidSignature(height, hash));
+                first_invalid = first_invalid.or(Some(height));
+            }
+        }
+    }
+
+    let mut new_height = headers.len() as u64 - 1;
+    if let Some(height) = first_invalid {
+        new_height = if height > 0 { height - 1 } else { 0 };
+    }
+    if !complete || Some(new_height) != old_height {
+        let hash = headers[new_height as usize].hash();
+        report.new_best = Some((new_height, hash));
+        batch.put(db::COL_EXTRA, b"current_hash", &hash);
+        for height in (new_height + 1)..(old_height.unwrap_or(new_height) + 1) {
+            batch.delete(db::COL_EXTRA, &Key::<H256>::key(&height));
+        }
+    }
+
+    if repair && !report.is_ok() {
+        db.write(batch).expect("DB write failed.");
+        report.repaired = true;
+    }
+    report
+}
+
+/// Follow the parent links from block `hash`. Returns the headers found,
+/// highest first, and whether they reach the genesis.
+fn walk(db: &KeyValueDB, mut hash: H256, issues: &mut Vec<Issue>) -> (Vec<RichHeader>, bool) {
+    let mut headers: Vec<RichHeader> = Vec::new();
+    loop {
+        let header: RichHeader = match db.read(db::COL_HEADERS, &hash) {
+            Some(header) => header,
+            None => {
+                issues.push(Issue::MissingHeader(hash));
+                return (headers, false);
+            }
+        };
+        if header.hash() != hash {
+            issues.push(Issue::HashMismatch(header.height, hash));
+            return (headers, false);
+        }
+        if let Some(child) = headers.last() {
+            if child.height != header.height + 1 {
+                issues.push(Issue::BrokenLink(child.height, child.hash()));
+                return (headers, false);
+            }
+        }
+
+        let height = header.height;
+        hash = header.parent_hash;
+        headers.push(header);
+        if height == 0 {
+            return (headers, true);
+        }
+    }
+}
+
+/// The highest chain below height `below` in the number index which reaches the genesis.
+fn recover(db: &KeyValueDB, below: BlockNumber) -> Option<Vec<RichHeader>> {
+    for height in (0..below).rev() {
+        let hash: Option<H256> = db.read(db::COL_EXTRA, &height);
+        if let Some(hash) = hash {
+            let (headers, complete) = walk(db, hash, &mut Vec::new());
+            if complete {
+                return Some(headers);
+            }
+        }
+    }
+    None
+}
+
+/// The highest height in the number index.
+fn highest_indexed(db: &KeyValueDB) -> Option<BlockNumber> {
+    let prefix = [ExtrasIndex::BlockHash as u8];
+    db.iter_from_prefix(db::COL_EXTRA, &prefix)
+        .take_while(|&(ref key, _)| key.len() == 5 && key[0] == prefix[0])
+        .map(|(key, _)| key[1..].iter().fold(0, |h, b| (h << 8) | *b as u64))
+        .last()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvdb::in_memory;
+    use block::Block;
+
+    fn store(db: &KeyValueDB, blocks: &[Block]) {
+        let mut batch = DBTransaction::new();
+        for block in blocks {
+            let rh = RichHeader { header: block.header.clone(), verified: true };
+            batch.write(db::COL_HEADERS, &block.hash(), &rh);
+            batch.write(db::COL_EXTRA, &block.height, &block.hash());
+        }
+        db.write(batch).unwrap();
+    }
+
+    #[test]
+    fn walk_and_recover() {
+        let db = in_memory(db::NUM_COLUMNS.unwrap());
+        let genesis = Block::genesis(1);
+        let b1 = Block::init(1, 2, genesis.hash(), Vec::new(), vec![1]);
+        let b2 = Block::init(2, 3, b1.hash(), Vec::new(), vec![2]);
+        let b3 = Block::init(3, 4, b2.hash(), Vec::new(), vec![3]);
+        store(&db, &[genesis.clone(), b1.clone(), b3.clone()]);
+
+        let mut issues = Vec::new();
+        let (headers, complete) = walk(&db, b1.hash(), &mut issues);
+        assert!(complete);
+        assert_eq!(headers.len(), 2);
+        assert!(issues.is_empty());
+
+        let (headers, complete) = walk(&db, b3.hash(), &mut issues);
+        assert!(!complete);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(issues, vec![Issue::MissingHeader(b2.hash())]);
+
+        assert_eq!(highest_indexed(&db), Some(3));
+        let headers = recover(&db, 3).unwrap();
+        assert_eq!(headers[0].hash(), b1.hash());
+    }
+}
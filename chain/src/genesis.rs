@@ -0,0 +1,74 @@
+//! Genesis block of a genesis spec.
+//!
+//! The chain parameters and the initial keygroups are written into the
+//! storage of the system account beside the allocations, so the hash of the
+//! genesis block commits to the whole spec.
+
+use std::sync::Arc;
+use kvdb::{DBTransaction, KeyValueDB};
+use rlp;
+use util::{Address, SHA3_NULL_RLP};
+use util::config::GenesisSpec;
+use block::Block;
+use state::State;
+use validators;
+use error::Error;
+
+/// Account holding the chain parameters.
+pub fn system_address() -> Address {
+    Address::default()
+}
+
+/// Build the genesis block of `spec` and put its state into `batch`.
+pub fn build(spec: &GenesisSpec, db: Arc<KeyValueDB>, batch: &mut DBTransaction) -> Result<Block, Error> {
+    let mut state = State::from_existing(db, SHA3_NULL_RLP)?;
+    let system = system_address();
+    state.set(&system, b"epoch_len", &rlp::encode(&spec.epoch_len))?;
+    state.set(&system, b"steps", &rlp::encode(&spec.steps))?;
+    state.set(&system, b"nps", &rlp::encode(&spec.nps))?;
+    let set = spec.keygroups.iter()
+        .map(|group| (group.signer_public_key, (group.proof_public_key.clone(), group.proof_public_g.clone())))
+        .collect();
+    state.set(&system, b"keygroups", &validators::encode(&set))?;
+    for alloc in &spec.alloc {
+        state.set(&alloc.address, &alloc.key, &alloc.value)?;
+    }
+    state.commit(batch);
+
+    let mut block = Block::genesis(spec.timestamp);
+    block.state_root = state.root();
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvdb::in_memory;
+    use util::config::Alloc;
+    use db;
+
+    #[test]
+    fn genesis_of_spec() {
+        let db: Arc<KeyValueDB> = Arc::new(in_memory(db::NUM_COLUMNS.unwrap()));
+        let mut spec = GenesisSpec {
+            timestamp: 1,
+            epoch_len: 10,
+            steps: 6,
+            nps: 10,
+            keygroups: Vec::new(),
+            alloc: vec![Alloc { address: Address::from(1), key: b"foo".to_vec(), value: b"bar".to_vec() }],
+        };
+        let mut batch = db.transaction();
+        let genesis = build(&spec, db.clone(), &mut batch).unwrap();
+        db.write(batch).unwrap();
+        assert!(genesis.hash() != Block::genesis(1).hash());
+
+        let state = State::from_existing(db.clone(), genesis.state_root).unwrap();
+        assert_eq!(state.get(&Address::from(1), b"foo").unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(state.get(&system_address(), b"nps").unwrap(), Some(rlp::encode(&10u64).to_vec()));
+
+        spec.epoch_len = 20;
+        let other = build(&spec, db, &mut DBTransaction::new()).unwrap();
+        assert!(other.hash() != genesis.hash());
+    }
+}
@@ -4,7 +4,6 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate parking_lot;
-extern crate rand;
 #[macro_use]
 extern crate log;
 extern crate bincode;
@@ -16,6 +15,8 @@ extern crate kvdb;
 extern crate heapsize;
 extern crate bigint;
 extern crate ethcore_bytes as bytes;
+#[cfg(any(test, feature = "test-helpers"))]
+extern crate toml;
 
 pub mod error;
 pub mod block;
@@ -25,3 +26,14 @@ pub mod extras;
 pub mod db;
 pub mod cache_manager;
 pub mod cache;
+pub mod receipt;
+pub mod state_db;
+pub mod state;
+pub mod event;
+pub mod fork_choice;
+pub mod export;
+pub mod fsck;
+pub mod genesis;
+pub mod validators;
+pub mod difficulty;
+pub mod evidence;
@@ -0,0 +1,94 @@
+//! Transaction receipts.
+
+use util::{H256, Address, Hashable, HeapSizeOf, complete_merkle_root_raw};
+use rlp;
+
+/// A log emitted while executing a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, RlpEncodable, RlpDecodable)]
+pub struct LogEntry {
+    /// The account whose storage was touched.
+    pub address: Address,
+    /// The storage key.
+    pub key: Vec<u8>,
+    /// The value written under the key, empty if it was removed.
+    pub data: Vec<u8>,
+}
+
+impl HeapSizeOf for LogEntry {
+    fn heap_size_of_children(&self) -> usize {
+        self.key.heap_size_of_children() + self.data.heap_size_of_children()
+    }
+}
+
+/// The outcome of executing a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, RlpEncodable, RlpDecodable)]
+pub struct Receipt {
+    /// Transaction hash.
+    pub transaction_hash: H256,
+    /// Height of the block which includes the transaction.
+    pub block_height: u64,
+    /// Transaction index within the block.
+    pub index: usize,
+    /// Whether the transaction was applied to the state.
+    pub success: bool,
+    /// Logs emitted by the transaction.
+    pub logs: Vec<LogEntry>,
+}
+
+impl HeapSizeOf for Receipt {
+    fn heap_size_of_children(&self) -> usize {
+        self.logs.heap_size_of_children()
+    }
+}
+
+/// Receipts of all transactions of a block, in order.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct BlockReceipts {
+    pub receipts: Vec<Receipt>,
+}
+
+impl BlockReceipts {
+    pub fn new(receipts: Vec<Receipt>) -> Self {
+        BlockReceipts { receipts: receipts }
+    }
+
+    ///calculate the receipts root
+    pub fn receipts_root(&self) -> H256 {
+        complete_merkle_root_raw(self.receipts.iter().map(|r| rlp::encode(r).sha3()).collect())
+    }
+}
+
+impl HeapSizeOf for BlockReceipts {
+    fn heap_size_of_children(&self) -> usize {
+        self.receipts.heap_size_of_children()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::SHA3_NULL_RLP;
+
+    #[test]
+    fn receipts_root() {
+        assert_eq!(BlockReceipts::new(Vec::new()).receipts_root(), SHA3_NULL_RLP);
+
+        let receipt = Receipt {
+            transaction_hash: H256::from(1),
+            block_height: 1,
+            index: 0,
+            success: true,
+            logs: vec![LogEntry { address: Address::from(1), key: vec![1], data: vec![2] }],
+        };
+        let mut failed = receipt.clone();
+        failed.success = false;
+        failed.logs.clear();
+
+        let root = BlockReceipts::new(vec![receipt.clone()]).receipts_root();
+        assert!(root != SHA3_NULL_RLP);
+        assert!(root != BlockReceipts::new(vec![failed]).receipts_root());
+
+        let decoded: Receipt = rlp::decode(&rlp::encode(&receipt));
+        assert_eq!(decoded, receipt);
+    }
+}
@@ -0,0 +1,162 @@
+//! Account state kept in an AVL trie.
+//!
+//! Every transaction carries an RLP encoded `StateOp` in its data. Applying it
+//! writes `value` under `key` in the storage of the sender's address; an empty
+//! value removes the key. The key `VALIDATOR_KEY` is reserved for validator
+//! transactions, see `validators`.
+
+use std::sync::Arc;
+use util::{H256, Address, HashDB};
+use util::avl::{AVLFactory, AVL, AVLError};
+use kvdb::{DBTransaction, KeyValueDB};
+use crypto::pubkey_to_address;
+use rlp::UntrustedRlp;
+use transaction::SignedTransaction;
+use receipt::LogEntry;
+use state_db::StateDB;
+use validators::{self, ValidatorOp, ValidatorSet, VALIDATOR_KEY};
+use error::Error;
+
+/// A key/value write carried in `Transaction::data`.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct StateOp {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl From<Box<AVLError>> for Error {
+    fn from(_: Box<AVLError>) -> Error {
+        Error::UnknownState
+    }
+}
+
+pub struct State {
+    db: StateDB,
+    root: H256,
+    factory: AVLFactory,
+}
+
+impl State {
+    /// Open the state at `root`.
+    pub fn from_existing(db: Arc<KeyValueDB>, root: H256) -> Result<State, Error> {
+        let db = StateDB::new(db);
+        if !db.contains(&root) {
+            return Err(Error::UnknownState);
+        }
+        Ok(State {
+            db: db,
+            root: root,
+            factory: AVLFactory::default(),
+        })
+    }
+
+    /// The root of the state including uncommitted changes.
+    pub fn root(&self) -> H256 {
+        self.root
+    }
+
+    fn storage_key(address: &Address, key: &[u8]) -> Vec<u8> {
+        let mut k = address.to_vec();
+        k.extend_from_slice(key);
+        k
+    }
+
+    /// Get the value stored under `key` by `address`.
+    pub fn get(&self, address: &Address, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let avl = self.factory.readonly(&self.db, &self.root)?;
+        let value = avl.get(&State::storage_key(address, key))?;
+        Ok(value.map(|v| v.to_vec()))
+    }
+
+    /// Set the value stored under `key` by `address`. An empty value removes the key.
+    pub fn set(&mut self, address: &Address, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let mut avl = self.factory.from_existing(&mut self.db, &mut self.root)?;
+        avl.insert(&State::storage_key(address, key), value)?;
+        Ok(())
+    }
+
+    /// Apply a transaction and return the logs it emitted.
+    /// A transaction which fails leaves the state untouched.
+    /// `validators` is the validator set of a state which has none written yet.
+    pub fn apply_transaction(&mut self, tx: &SignedTransaction, validators: &ValidatorSet) -> Result<Vec<LogEntry>, Error> {
+        let public = tx.recover_public()?;
+        let sender = pubkey_to_address(&public);
+        let op: StateOp = UntrustedRlp::new(&tx.data).as_val().map_err(|_| Error::InvalidFormat)?;
+        if op.key == VALIDATOR_KEY {
+            let validator_op: ValidatorOp = UntrustedRlp::new(&op.value).as_val().map_err(|_| Error::InvalidFormat)?;
+            validators::apply(self, &public, &validator_op, validators)?;
+        } else {
+            self.set(&sender, &op.key, &op.value)?;
+        }
+        Ok(vec![LogEntry { address: sender, key: op.key, data: op.value }])
+    }
+
+    /// Move the new state nodes into `batch`.
+    pub fn commit(&mut self, batch: &mut DBTransaction) {
+        self.db.commit(batch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvdb::in_memory;
+    use rlp;
+    use db;
+    use std::collections::HashMap;
+    use util::SHA3_NULL_RLP;
+    use crypto::KeyPair;
+    use transaction::Transaction;
+
+    fn new_db() -> Arc<KeyValueDB> {
+        Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))
+    }
+
+    #[test]
+    fn state_roots() {
+        let db = new_db();
+        let address = Address::from(1);
+        let mut state = State::from_existing(db.clone(), SHA3_NULL_RLP).unwrap();
+        state.set(&address, b"foo", b"bar").unwrap();
+        assert_eq!(state.get(&address, b"foo").unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(state.get(&Address::from(2), b"foo").unwrap(), None);
+        let mut batch = db.transaction();
+        state.commit(&mut batch);
+        db.write(batch).unwrap();
+        let root1 = state.root();
+        assert!(root1 != SHA3_NULL_RLP);
+
+        state.set(&address, b"foo", b"baz").unwrap();
+        let mut batch = db.transaction();
+        state.commit(&mut batch);
+        db.write(batch).unwrap();
+        let root2 = state.root();
+
+        let old = State::from_existing(db.clone(), root1).unwrap();
+        assert_eq!(old.get(&address, b"foo").unwrap(), Some(b"bar".to_vec()));
+        let new = State::from_existing(db.clone(), root2).unwrap();
+        assert_eq!(new.get(&address, b"foo").unwrap(), Some(b"baz".to_vec()));
+
+        assert!(State::from_existing(db, H256::from(3)).is_err());
+    }
+
+    #[test]
+    fn apply_transaction() {
+        let db = new_db();
+        let private_key = H256::from("40f2d8f8e1594579824fd04edfc7ff1ddffd6be153b23f4318e1acff037d3ea9");
+        let address = KeyPair::from_privkey(private_key).unwrap().address();
+        let mut state = State::from_existing(db, SHA3_NULL_RLP).unwrap();
+
+        let mut tx = Transaction::new(1);
+        tx.set_data(rlp::encode(&StateOp { key: b"foo".to_vec(), value: b"bar".to_vec() }).to_vec());
+        let logs = state.apply_transaction(&tx.sign(&private_key), &HashMap::new()).unwrap();
+        assert_eq!(logs, vec![LogEntry { address: address, key: b"foo".to_vec(), data: b"bar".to_vec() }]);
+        assert_eq!(state.get(&address, b"foo").unwrap(), Some(b"bar".to_vec()));
+
+        let root = state.root();
+        let mut bad = Transaction::new(2);
+        bad.set_data(vec![1, 2, 3]);
+        assert_eq!(state.apply_transaction(&bad.sign(&private_key), &HashMap::new()), Err(Error::InvalidFormat));
+        assert_eq!(state.root(), root);
+    }
+}
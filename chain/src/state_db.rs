@@ -0,0 +1,67 @@
+//! `HashDB` view of the state column.
+
+use std::sync::Arc;
+use std::collections::HashMap;
+use util::{H256, HashDB, DBValue};
+use util::memorydb::MemoryDB;
+use kvdb::{DBTransaction, KeyValueDB};
+use db;
+
+/// Archive `HashDB` over `COL_STATE`.
+///
+/// New nodes are kept in an overlay until `commit` moves them into a batch.
+/// Nodes are never deleted from the column, so the state of any block stays
+/// readable through its `state_root`.
+pub struct StateDB {
+    db: Arc<KeyValueDB>,
+    overlay: MemoryDB,
+}
+
+impl StateDB {
+    pub fn new(db: Arc<KeyValueDB>) -> Self {
+        StateDB {
+            db: db,
+            overlay: MemoryDB::new(),
+        }
+    }
+
+    /// Write the pending nodes into `batch`.
+    pub fn commit(&mut self, batch: &mut DBTransaction) {
+        for (key, (value, rc)) in self.overlay.drain() {
+            if rc > 0 {
+                batch.put(db::COL_STATE, &key, &value);
+            }
+        }
+    }
+}
+
+impl HashDB for StateDB {
+    fn keys(&self) -> HashMap<H256, i32> {
+        self.overlay.keys()
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        if let Some((value, rc)) = self.overlay.raw(key) {
+            if rc > 0 {
+                return Some(value);
+            }
+        }
+        self.db.get(db::COL_STATE, key).expect("Low-level database error.")
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn insert(&mut self, value: &[u8]) -> H256 {
+        self.overlay.insert(value)
+    }
+
+    fn emplace(&mut self, key: H256, value: DBValue) {
+        self.overlay.emplace(key, value);
+    }
+
+    fn remove(&mut self, key: &H256) {
+        self.overlay.remove(key);
+    }
+}
@@ -0,0 +1,86 @@
+//! Fixtures for the tests of the chain and of the crates built on it, which
+//! get them with the `test-helpers` feature.
+
+use std::sync::Arc;
+use parking_lot::RwLock;
+use toml;
+use bls;
+use crypto::KeyPair;
+use kvdb::{in_memory, DBTransaction};
+use util::hash::H256;
+use util::config::{Config, SleepyConfig};
+use util::timesource::MockTime;
+use block::Block;
+use transaction::SignedTransaction;
+use db;
+use super::Chain;
+
+/// Signer key of the single validator.
+pub const SIGNER: &'static str = "40f2d8f8e1594579824fd04edfc7ff1ddffd6be153b23f4318e1acff037d3ea9";
+
+/// Time of the mock clock, far after the blocks of the tests.
+pub const NOW: i64 = 1000000 * 1000000000;
+
+/// Config of epochs of 4 blocks with the single validator `SIGNER` and a
+/// fresh proof key, `extra` is more of it.
+pub fn new_config(extra: &str, time: Arc<MockTime>) -> Arc<RwLock<SleepyConfig>> {
+    let signer = KeyPair::from_privkey(H256::from(SIGNER)).unwrap();
+    let (miner_private_key, proof_public_key, proof_public_g) = bls::key_gen().unwrap();
+    let toml = format!(r#"
+        id_card = 0
+        port = 40000
+        max_peer = 1
+        nps = 10
+        steps = 6
+        epoch_len = 4
+        start_time = 1
+        miner_private_key = {:?}
+        signer_private_key = "{}"
+        ntp_servers = []
+        buffer_size = 5
+        peers = []
+        {}
+        [[keygroups]]
+        proof_public_key = {:?}
+        proof_public_g = {:?}
+        signer_public_key = "{:?}"
+    "#, miner_private_key, SIGNER, extra, proof_public_key, proof_public_g, signer.pubkey());
+    let config: Config = toml::from_str(&toml).unwrap();
+    Arc::new(RwLock::new(SleepyConfig::with_time_source(config, time)))
+}
+
+/// A chain in memory of `new_config(extra)` at `NOW`.
+pub fn new_chain(extra: &str) -> Arc<Chain> {
+    let config = new_config(extra, Arc::new(MockTime::new(NOW)));
+    Chain::init(config, Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap()
+}
+
+/// A block of `txs` on top of `parent` good enough to be inserted, not inserted.
+pub fn valid_block(chain: &Chain, parent: H256, txs: Vec<SignedTransaction>) -> Block {
+    let miner_private_key = chain.config.read().get_miner_private_key();
+    let parent = chain.get_block_header_by_hash(&parent).unwrap();
+    let height = parent.height + 1;
+    let anc_hash = chain.anc_hash(parent.height, parent.hash()).unwrap();
+    let target = chain.difficulty(height, parent.hash()).unwrap();
+    let mut timestamp = parent.timestamp;
+    loop {
+        timestamp += 1;
+        let proof = Block::gen_proof(miner_private_key.clone(), timestamp, height, anc_hash).unwrap();
+        let mut block = Block::init(height, timestamp, parent.hash(), txs.clone(), proof);
+        if block.difficulty() <= target {
+            let (state_root, receipts) = chain.execute_transactions(&parent.hash(), height, &txs, &mut DBTransaction::new()).unwrap();
+            block.state_root = state_root;
+            block.receipts_root = receipts.receipts_root();
+            block.sign(&H256::from(SIGNER));
+            return block;
+        }
+    }
+}
+
+/// Insert a valid empty block on top of the best one.
+pub fn mine(chain: &Chain) -> Block {
+    let (_, best) = chain.get_status();
+    let block = valid_block(chain, best, Vec::new());
+    chain.insert(block.clone()).unwrap();
+    block
+}
@@ -1,6 +1,6 @@
 use util::{H256, H512, H520, Hashable, HeapSizeOf};
 use std::ops::{Deref, DerefMut};
-use crypto::{recover, Signature};
+use crypto::{recover, sign, Signature};
 use error::Error;
 use rlp;
 
@@ -9,6 +9,8 @@ pub struct Transaction {
     /// Transaction data.
     pub data: Vec<u8>,
     pub timestamp: u64,
+    /// Transactions with a higher priority are packaged first by a `PRIORITY` pool.
+    pub priority: u64,
 }
 
 impl HeapSizeOf for Transaction {
@@ -21,7 +23,8 @@ impl Transaction {
     pub fn new(t: u64) -> Self {
         Transaction {
             timestamp: t,
-            data: Vec::new()
+            data: Vec::new(),
+            priority: 0,
         }
     }
 
@@ -33,6 +36,22 @@ impl Transaction {
     pub fn set_data(&mut self, data: Vec<u8>) {
         self.data = data;
     }
+
+    ///set priority
+    pub fn set_priority(&mut self, priority: u64) {
+        self.priority = priority;
+    }
+
+    ///sign transaction
+    pub fn sign(self, private_key: &H256) -> SignedTransaction {
+        let hash = self.cal_hash();
+        let signature = sign(private_key, &hash).unwrap().into();
+        SignedTransaction {
+            transaction: self,
+            hash: hash,
+            signature: signature,
+        }
+    }
 }
 
 #[derive(Hash, Clone, Serialize, Deserialize, PartialEq, Eq, Debug, RlpEncodable, RlpDecodable)]
@@ -0,0 +1,303 @@
+//! Validator set managed by transactions.
+//!
+//! A transaction whose `StateOp` key is `VALIDATOR_KEY` carries a
+//! `ValidatorOp` as its value instead of a storage write, and changes the set
+//! kept in the storage of the system account. A sender only registers or
+//! retires its own signer key, so no validator can remove another, and a
+//! registration must carry the approval of a validator already in the set, so
+//! that nobody can join by themselves. The blocks of an epoch are checked against the set in the state of the last
+//! block of the epoch before, so a change takes effect at the next epoch
+//! boundary.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use kvdb::KeyValueDB;
+use rlp::{Encodable, Decodable, RlpStream, UntrustedRlp, DecoderError};
+use util::{H256, H512, H520, Hashable};
+use crypto::{sign, recover, Signature};
+use genesis::system_address;
+use state::State;
+use error::Error;
+
+/// Key of the `StateOp` of a validator transaction.
+pub const VALIDATOR_KEY: &'static [u8] = b"validator";
+
+/// Length of a compressed proof public key or generator.
+pub const PROOF_KEY_LEN: usize = bls::PUBLIC_KEY_LEN;
+
+/// Proof public key and generator by signer public key.
+pub type ValidatorSet = HashMap<H512, (Vec<u8>, Vec<u8>)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidatorOp {
+    /// Add the sender as a validator with this proof key.
+    Register {
+        proof_public_key: Vec<u8>,
+        proof_public_g: Vec<u8>,
+        signer_public_key: H512,
+        /// Signature of `registration_hash` by a validator of the set.
+        approval: H520,
+    },
+    /// Remove the sender from the validators.
+    Retire(H512),
+}
+
+impl ValidatorOp {
+    /// A registration of `signer_public_key` approved by the validator `approver`.
+    pub fn register(proof_public_key: Vec<u8>, proof_public_g: Vec<u8>, signer_public_key: H512, approver: &H256) -> ValidatorOp {
+        let hash = registration_hash(&proof_public_key, &proof_public_g, &signer_public_key);
+        ValidatorOp::Register {
+            proof_public_key,
+            proof_public_g,
+            signer_public_key,
+            approval: sign(approver, &hash).unwrap().into(),
+        }
+    }
+}
+
+/// The hash a validator signs to approve a registration.
+pub fn registration_hash(proof_public_key: &[u8], proof_public_g: &[u8], signer_public_key: &H512) -> H256 {
+    let mut s = RlpStream::new_list(3);
+    s.append(&proof_public_key);
+    s.append(&proof_public_g);
+    s.append(signer_public_key);
+    s.out().sha3()
+}
+
+impl Encodable for ValidatorOp {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match *self {
+            ValidatorOp::Register { ref proof_public_key, ref proof_public_g, ref signer_public_key, ref approval } => {
+                s.begin_list(5);
+                s.append(&0u8);
+                s.append(proof_public_key);
+                s.append(proof_public_g);
+                s.append(signer_public_key);
+                s.append(approval);
+            }
+            ValidatorOp::Retire(ref signer_public_key) => {
+                s.begin_list(2);
+                s.append(&1u8);
+                s.append(signer_public_key);
+            }
+        }
+    }
+}
+
+impl Decodable for ValidatorOp {
+    fn decode(r: &UntrustedRlp) -> Result<Self, DecoderError> {
+        let tag: u8 = r.val_at(0)?;
+        match (tag, r.item_count()?) {
+            (0, 5) => Ok(ValidatorOp::Register {
+                proof_public_key: r.val_at(1)?,
+                proof_public_g: r.val_at(2)?,
+                signer_public_key: r.val_at(3)?,
+                approval: r.val_at(4)?,
+            }),
+            (1, 2) => Ok(ValidatorOp::Retire(r.val_at(1)?)),
+            _ => Err(DecoderError::Custom("unknown validator op")),
+        }
+    }
+}
+
+/// Height of the block whose state holds the validators of the block at `height`.
+pub fn set_height(height: u64, epoch_len: u64) -> u64 {
+    let start = height / epoch_len * epoch_len;
+    if start > 0 { start - 1 } else { 0 }
+}
+
+/// Encode the set in signer key order, so that every node gets the same state.
+pub fn encode(set: &ValidatorSet) -> Vec<u8> {
+    let mut signers: Vec<&H512> = set.keys().collect();
+    signers.sort();
+    let mut s = RlpStream::new_list(signers.len());
+    for signer in signers {
+        let &(ref proof_public_key, ref proof_public_g) = &set[signer];
+        s.begin_list(3);
+        s.append(proof_public_key);
+        s.append(proof_public_g);
+        s.append(signer);
+    }
+    s.out()
+}
+
+pub fn decode(data: &[u8]) -> Result<ValidatorSet, Error> {
+    let mut set = HashMap::new();
+    for item in UntrustedRlp::new(data).iter() {
+        let signer: H512 = item.val_at(2).map_err(|_| Error::InvalidFormat)?;
+        let proof_public_key: Vec<u8> = item.val_at(0).map_err(|_| Error::InvalidFormat)?;
+        let proof_public_g: Vec<u8> = item.val_at(1).map_err(|_| Error::InvalidFormat)?;
+        set.insert(signer, (proof_public_key, proof_public_g));
+    }
+    Ok(set)
+}
+
+/// The set in `state`, `None` if it was never written.
+pub fn read(state: &State) -> Result<Option<ValidatorSet>, Error> {
+    match state.get(&system_address(), b"keygroups")? {
+        Some(data) => decode(&data).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The set in the state `root`, `initial` if the state has none.
+pub fn at(db: Arc<KeyValueDB>, root: H256, initial: &ValidatorSet) -> Result<ValidatorSet, Error> {
+    let state = State::from_existing(db, root)?;
+    Ok(read(&state)?.unwrap_or_else(|| initial.clone()))
+}
+
+/// Apply `op` sent by `sender`. The key of the op must be the sender's,
+/// a registration must be approved by a validator of the set, a registered
+/// key cannot be replaced and the last validator cannot retire.
+pub fn apply(state: &mut State, sender: &H512, op: &ValidatorOp, initial: &ValidatorSet) -> Result<(), Error> {
+    let mut set = read(state)?.unwrap_or_else(|| initial.clone());
+    match *op {
+        ValidatorOp::Register { ref proof_public_key, ref proof_public_g, ref signer_public_key, ref approval } => {
+            if signer_public_key != sender {
+                return Err(Error::InvalidPublicKey);
+            }
+            if set.contains_key(sender) {
+                return Err(Error::DuplicateValidator);
+            }
+            let hash = registration_hash(proof_public_key, proof_public_g, signer_public_key);
+            let approval: Signature = approval.clone().into();
+            match recover(&approval, &hash) {
+                Ok(ref approver) if set.contains_key(approver) => {}
+                _ => return Err(Error::UnauthorizedValidator),
+            }
+            if proof_public_key.len() != PROOF_KEY_LEN || proof_public_g.len() != PROOF_KEY_LEN {
+                return Err(Error::InvalidProofKey);
+            }
+            set.insert(*sender, (proof_public_key.clone(), proof_public_g.clone()));
+        }
+        ValidatorOp::Retire(ref signer_public_key) => {
+            if signer_public_key != sender {
+                return Err(Error::InvalidPublicKey);
+            }
+            if set.remove(sender).is_none() {
+                return Err(Error::NotValidator);
+            }
+            if set.is_empty() {
+                return Err(Error::LastValidator);
+            }
+        }
+    }
+    state.set(&system_address(), b"keygroups", &encode(&set))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlp;
+    use kvdb::in_memory;
+    use util::SHA3_NULL_RLP;
+    use crypto::KeyPair;
+    use db;
+
+    fn keys(i: u8) -> (Vec<u8>, Vec<u8>) {
+        (vec![i; PROOF_KEY_LEN], vec![i + 1; PROOF_KEY_LEN])
+    }
+
+    /// Private and public signer key of validator `i`.
+    fn signer(i: u64) -> (H256, H512) {
+        let private_key = H256::from(i);
+        (private_key, *KeyPair::from_privkey(private_key).unwrap().pubkey())
+    }
+
+    fn register(i: u8, approver: u64) -> ValidatorOp {
+        let (proof_public_key, proof_public_g) = keys(i);
+        ValidatorOp::register(proof_public_key, proof_public_g, signer(i as u64).1, &signer(approver).0)
+    }
+
+    fn new_state() -> State {
+        let db: Arc<KeyValueDB> = Arc::new(in_memory(db::NUM_COLUMNS.unwrap()));
+        State::from_existing(db, SHA3_NULL_RLP).unwrap()
+    }
+
+    #[test]
+    fn op_rlp() {
+        let ops = vec![
+            register(2, 1),
+            ValidatorOp::Retire(H512::from(3)),
+        ];
+        for op in ops {
+            assert_eq!(rlp::decode::<ValidatorOp>(&rlp::encode(&op)), op);
+        }
+    }
+
+    #[test]
+    fn set_height_of_epoch() {
+        assert_eq!(set_height(3, 10), 0);
+        assert_eq!(set_height(10, 10), 9);
+        assert_eq!(set_height(19, 10), 9);
+        assert_eq!(set_height(20, 10), 19);
+    }
+
+    #[test]
+    fn register_and_retire() {
+        let mut state = new_state();
+        let (v1, v2) = (signer(1).1, signer(2).1);
+        let mut initial = HashMap::new();
+        initial.insert(v1, keys(1));
+        assert_eq!(read(&state).unwrap(), None);
+
+        let register = register(2, 1);
+        apply(&mut state, &v2, &register, &initial).unwrap();
+        let set = read(&state).unwrap().unwrap();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set[&v2], keys(2));
+        assert_eq!(apply(&mut state, &v2, &register, &initial), Err(Error::DuplicateValidator));
+
+        apply(&mut state, &v1, &ValidatorOp::Retire(v1), &initial).unwrap();
+        assert_eq!(apply(&mut state, &v1, &ValidatorOp::Retire(v1), &initial), Err(Error::NotValidator));
+        assert_eq!(apply(&mut state, &v2, &ValidatorOp::Retire(v2), &initial), Err(Error::LastValidator));
+        let set = read(&state).unwrap().unwrap();
+        assert_eq!(set.keys().collect::<Vec<_>>(), vec![&v2]);
+    }
+
+    #[test]
+    fn only_own_key() {
+        let mut state = new_state();
+        let (v1, v2) = (signer(1).1, signer(2).1);
+        let mut initial = HashMap::new();
+        initial.insert(v1, keys(1));
+        initial.insert(v2, keys(2));
+        let root = state.root();
+
+        // a validator can neither retire another one
+        assert_eq!(apply(&mut state, &v1, &ValidatorOp::Retire(v2), &initial), Err(Error::InvalidPublicKey));
+        // nor register a key of someone else
+        assert_eq!(apply(&mut state, &v1, &register(3, 1), &initial), Err(Error::InvalidPublicKey));
+        // nor replace the proof key of another one, or its own
+        let (proof_public_key, proof_public_g) = keys(4);
+        for i in &[1, 2] {
+            let replace = ValidatorOp::register(proof_public_key.clone(), proof_public_g.clone(), signer(*i).1, &signer(1).0);
+            assert!(apply(&mut state, &v1, &replace, &initial).is_err());
+        }
+        assert_eq!(state.root(), root);
+        assert_eq!(read(&state).unwrap(), None);
+    }
+
+    #[test]
+    fn unauthorized_register() {
+        let mut state = new_state();
+        let v3 = signer(3).1;
+        let mut initial = HashMap::new();
+        initial.insert(signer(1).1, keys(1));
+
+        // approved by itself or by someone outside the set
+        for approver in &[3, 4] {
+            assert_eq!(apply(&mut state, &v3, &register(3, *approver), &initial), Err(Error::UnauthorizedValidator));
+        }
+        // an approval of other proof keys
+        let mut forged = register(3, 1);
+        if let ValidatorOp::Register { ref mut proof_public_key, .. } = forged {
+            *proof_public_key = keys(5).0;
+        }
+        assert_eq!(apply(&mut state, &v3, &forged, &initial), Err(Error::UnauthorizedValidator));
+        assert_eq!(read(&state).unwrap(), None);
+
+        apply(&mut state, &v3, &register(3, 1), &initial).unwrap();
+        assert_eq!(read(&state).unwrap().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,35 @@
+use secp256k1::ecdh::SharedSecret;
+use secp256k1::key::{SecretKey, PublicKey};
+use util::hash::H256;
+use super::{PrivKey, PubKey, SECP256K1, Error};
+
+/// Agree on a shared secret with the owner of `pubkey`.
+pub fn agree(privkey: &PrivKey, pubkey: &PubKey) -> Result<H256, Error> {
+    let context = &SECP256K1;
+    let pdata: [u8; 65] = {
+        let mut temp = [4u8; 65];
+        temp[1..65].copy_from_slice(pubkey);
+        temp
+    };
+
+    let publ = PublicKey::from_slice(context, &pdata)?;
+    let sec = SecretKey::from_slice(context, &privkey.0[..])?;
+    let shared = SharedSecret::new_raw(context, &publ, &sec);
+    Ok(H256::from_slice(&shared[0..32]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::agree;
+    use super::super::KeyPair;
+
+    #[test]
+    fn shared_secret() {
+        let a = KeyPair::random();
+        let b = KeyPair::random();
+        let c = KeyPair::random();
+        let ab = agree(a.privkey(), b.pubkey()).unwrap();
+        assert_eq!(ab, agree(b.privkey(), a.pubkey()).unwrap());
+        assert!(ab != agree(a.privkey(), c.pubkey()).unwrap());
+    }
+}
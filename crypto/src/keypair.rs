@@ -1,5 +1,6 @@
 use std::fmt;
 use secp256k1::key;
+use rand::os::OsRng;
 use rustc_serialize::hex::ToHex;
 use super::{PrivKey, PubKey, Address, SECP256K1, Error};
 use util::hash::{H160, H256};
@@ -50,6 +51,14 @@ impl KeyPair {
         Ok(keypair)
     }
 
+    /// Create a new random pair
+    pub fn random() -> Self {
+        let context = &SECP256K1;
+        let mut rng = OsRng::new().unwrap();
+        let (sec, publ) = context.generate_keypair(&mut rng).unwrap();
+        KeyPair::from_keypair(sec, publ)
+    }
+
     pub fn from_keypair(sec: key::SecretKey, publ: key::PublicKey) -> Self {
         let context = &SECP256K1;
         let serialized = publ.serialize_vec(context, false);
@@ -13,11 +13,13 @@ pub type Address = H160;
 
 mod error;
 mod keypair;
+mod ecdh;
 mod signature;
 mod signer;
 
 pub use self::error::*;
 pub use self::keypair::*;
+pub use self::ecdh::agree;
 pub use self::signature::*;
 pub use self::signer::Signer;
 use util::hash::{H160, H256, H512};
@@ -12,6 +12,8 @@ extern crate miner;
 extern crate parking_lot;
 extern crate tx_pool;
 extern crate kvdb;
+extern crate sync;
+extern crate rpc;
 
 use env_logger::LogBuilder;
 use std::env;
@@ -21,19 +23,28 @@ use network::server::start_server;
 use network::connection::{start_client, Operation};
 use network::msgclass::MsgClass;
 use std::sync::mpsc::channel;
-use clap::App;
+use clap::{App, ArgMatches, SubCommand};
 use std::time::Duration;
 use std::thread;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::process;
 use bincode::{serialize, deserialize, Infinite};
 use miner::start_miner;
 use chain::chain::Chain;
 use chain::error::Error;
+use chain::event::ChainEvent;
+use chain::export;
+use chain::fsck;
 use std::sync::Arc;
 use parking_lot::RwLock;
 use tx_pool::Pool;
+use tx_pool::journal::Journal;
 use util::datapath::DataPath;
 use kvdb::{Database, DatabaseConfig};
 use chain::db;
+use sync::start_sync;
+use rpc::{start_rpc, RpcHandler};
 
 pub fn log_init() {
     let format = |record: &LogRecord| {
@@ -55,6 +66,87 @@ pub fn log_init() {
     builder.init().unwrap();
 }
 
+fn init_chain(config: Arc<RwLock<SleepyConfig>>, db: Arc<Database>) -> Arc<Chain> {
+    match Chain::init(config, db) {
+        Ok(chain) => chain,
+        Err(Error::GenesisMismatch) => {
+            error!("the data directory belongs to a chain with another genesis");
+            process::exit(1);
+        }
+        Err(Error::OutdatedDatabase) => {
+            error!("the data directory was written by an earlier version with another block hash, sync into an empty one");
+            process::exit(1);
+        }
+        Err(Error::UnknownForkChoice(rule)) => {
+            error!("unknown fork_choice {:?} in config, use lowest_difficulty or earliest_timestamp", rule);
+            process::exit(1);
+        }
+        Err(e) => {
+            error!("load chain error {:?}, run `sleepy fsck --repair`", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn check_chain(db: Database, config: &SleepyConfig, repair: bool) -> i32 {
+    let report = fsck::check(Arc::new(db), config, repair);
+    for issue in &report.issues {
+        warn!("{:?}", issue);
+    }
+    if let Some((height, hash)) = report.new_best {
+        warn!("best block should move to {} {:?}", height, hash);
+    }
+    info!("checked {} blocks, {} issues{}",
+          report.checked,
+          report.issues.len(),
+          if report.repaired { ", repaired" } else { "" });
+    if report.is_ok() || report.repaired { 0 } else { 1 }
+}
+
+fn export_chain(chain: &Chain, m: &ArgMatches) -> i32 {
+    let from = m.value_of("from").map_or(0, |h| h.parse().expect("invalid --from"));
+    let to = m.value_of("to").map_or(chain.current_height(), |h| h.parse().expect("invalid --to"));
+    let path = m.value_of("FILE").unwrap();
+    let file = match File::create(path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("create {} error {}", path, e);
+            return 1;
+        }
+    };
+    match export::export(chain, from, to, &mut BufWriter::new(file)) {
+        Ok(n) => {
+            info!("export {} blocks to {}", n, path);
+            0
+        }
+        Err(e) => {
+            error!("export error {}", e);
+            1
+        }
+    }
+}
+
+fn import_chain(chain: &Chain, m: &ArgMatches) -> i32 {
+    let path = m.value_of("FILE").unwrap();
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("open {} error {}", path, e);
+            return 1;
+        }
+    };
+    match export::import(chain, &mut BufReader::new(file)) {
+        Ok(n) => {
+            info!("import {} blocks, best block {:?}", n, chain.get_status());
+            0
+        }
+        Err(e) => {
+            error!("import error {}", e);
+            1
+        }
+    }
+}
+
 fn main() {
     env::set_var("RUST_BACKTRACE", "full");
 
@@ -67,6 +159,17 @@ fn main() {
         .author("Cryptape")
         .about("Sleepy Node powered by Rust")
         .args_from_usage("-c, --config=[FILE] 'Sets a custom config file'")
+        .subcommand(SubCommand::with_name("export")
+                        .about("Export the best chain to a file")
+                        .args_from_usage("--from=[HEIGHT] 'First block, the genesis if not set'
+                                          --to=[HEIGHT] 'Last block, the best block if not set'
+                                          <FILE> 'Output file'"))
+        .subcommand(SubCommand::with_name("import")
+                        .about("Import and verify the blocks of an exported file")
+                        .args_from_usage("<FILE> 'Input file'"))
+        .subcommand(SubCommand::with_name("fsck")
+                        .about("Check the best chain in the database")
+                        .args_from_usage("--repair 'Fix the indexes and move the best block below broken blocks'"))
         .get_matches();
 
     let mut config_path = "config";
@@ -83,6 +186,21 @@ fn main() {
     let db_config = DatabaseConfig::with_columns(db::NUM_COLUMNS);
     let db = Database::open(&db_config, &nosql_path).unwrap();
 
+    match matches.subcommand() {
+        ("export", Some(m)) => {
+            let chain = init_chain(Arc::new(RwLock::new(config)), Arc::new(db));
+            process::exit(export_chain(&chain, m));
+        }
+        ("import", Some(m)) => {
+            let chain = init_chain(Arc::new(RwLock::new(config)), Arc::new(db));
+            process::exit(import_chain(&chain, m));
+        }
+        ("fsck", Some(m)) => {
+            process::exit(check_chain(db, &config, m.is_present("repair")));
+        }
+        _ => {}
+    }
+
     let (stx, srx) = channel();
 
     // start server
@@ -103,20 +221,80 @@ fn main() {
     let db = Arc::new(db);
 
     // init chain
-    let chain = Chain::init(config.clone(), db);
+    let chain = init_chain(config.clone(), db);
 
     // init tx pool
-    let tx_pool = Pool::new(1000, 300);
+    let mut tx_pool = match Pool::from_config(&config.read().tx_pool) {
+        Ok(pool) => pool,
+        Err(e) => {
+            error!("{} in config, use fifo, priority or vip", e);
+            process::exit(1);
+        }
+    };
+    let txpool_path = DataPath::txpool_path();
+    let txpool_db = Database::open(&DatabaseConfig::default(), &txpool_path).unwrap();
+    let restored = tx_pool.restore(Journal::new(Arc::new(txpool_db), None), &chain);
+    info!("restore {} txs from pool journal", restored);
     let tx_pool = Arc::new(RwLock::new(tx_pool));
 
+    // keep tx pool in step with the best chain
+    let events = chain.subscribe();
+    let chain1 = chain.clone();
+    let tx_pool1 = tx_pool.clone();
+    let ctx1 = ctx.clone();
+    let id = config.read().get_id();
+    thread::spawn(move || for event in events.iter() {
+                      match event {
+                          ChainEvent::Reorg { retracted, .. } => {
+                              let txs = retracted.iter()
+                                  .filter_map(|hash| chain1.get_block_body_by_hash(hash))
+                                  .flat_map(|body| body.transactions.into_iter())
+                                  .collect();
+                              let n = { tx_pool1.write().reinject(txs, &chain1) };
+                              info!("reinject {} txs", n);
+                          }
+                          ChainEvent::TxIncluded { hash, .. } => {
+                              tx_pool1.write().update(&[hash]);
+                          }
+                          ChainEvent::Equivocation(evidence) => {
+                              let message = serialize(&MsgClass::EVIDENCE(evidence), Infinite).unwrap();
+                              ctx1.send((id, Operation::BROADCAST, message)).unwrap();
+                          }
+                          ChainEvent::NewBest { .. } => {}
+                      }
+                  });
+
     // start miner
-    start_miner(ctx.clone(), chain.clone(), config.clone(), tx_pool.clone());
+    let (observer, mining) = {
+        let config = config.read();
+        (config.observer, config.mining)
+    };
+    let miner = if observer {
+        info!("observer, not mining");
+        None
+    } else {
+        Some(Arc::new(start_miner(ctx.clone(), chain.clone(), config.clone(), tx_pool.clone(), !mining)))
+    };
+
+    // start sync
+    let sync = start_sync(ctx.clone(), chain.clone(), config.read().get_id());
+
+    // start rpc
+    let rpc_port = { config.read().rpc_port };
+    if let Some(port) = rpc_port {
+        let handler = RpcHandler::new(ctx.clone(), chain.clone(), tx_pool.clone(), miner.clone(), config.read().get_id());
+        start_rpc(port, Arc::new(handler));
+    }
     
     //garbage collect
     let chain1 = chain.clone();
+    let tx_pool1 = tx_pool.clone();
     thread::spawn(move || loop {
                       thread::sleep(Duration::from_millis(100000));
+                      chain1.prune();
                       chain1.collect_garbage();
+                      let expired = { tx_pool1.write().expire(chain1.expire_timestamp()) };
+                      trace!("expire {} txs", expired);
                   });
 
     loop {
@@ -126,9 +304,20 @@ fn main() {
         match decoded {
             MsgClass::BLOCK(blk) => {
                 trace!("get block {} from {}", blk.height, origin);
+                if sync.is_syncing() {
+                    trace!("syncing, skip block {}", blk.height);
+                    continue;
+                }
                 let ret = chain.insert(blk.clone());
                 match ret {
-                    Ok(_) => {}
+                    Ok(_) => {
+                        // observers pass the blocks on to the nodes behind them,
+                        // not back to the one it came from
+                        if observer {
+                            let message = serialize(&MsgClass::BLOCK(blk), Infinite).unwrap();
+                            ctx.send((origin, Operation::SUBTRACT, message)).unwrap();
+                        }
+                    }
                     Err(err) => {
                         if err != Error::DuplicateBlock {
                             warn!("insert block error {:?}", err);
@@ -159,7 +348,7 @@ fn main() {
                 if ret.is_ok() {
                     let hash = stx.hash();             
                     let ret = { tx_pool.write().enqueue(stx.clone(), hash) };
-                    if ret {
+                    if ret.is_ok() {
                         let message = serialize(&MsgClass::TX(stx), Infinite).unwrap();
                         ctx.send((origin, Operation::BROADCAST, message)).unwrap();
                     }
@@ -170,6 +359,32 @@ fn main() {
             MsgClass::MSG(m) => {
                 trace!("get msg {:?}", m);
             }
+            MsgClass::STATUS(height, hash) => {
+                trace!("get status {} {:?} from {}", height, hash, origin);
+                sync.on_status(origin, height, hash);
+            }
+            MsgClass::GETHEADERS(from, count) => {
+                trace!("request {} headers from {}", count, from);
+                sync.on_get_headers(origin, from, count);
+            }
+            MsgClass::HEADERS(headers) => {
+                trace!("get {} headers from {}", headers.len(), origin);
+                sync.on_headers(origin, headers);
+            }
+            MsgClass::GETBODIES(hashes) => {
+                trace!("request {} bodies", hashes.len());
+                sync.on_get_bodies(origin, hashes);
+            }
+            MsgClass::BODIES(bodies) => {
+                trace!("get {} bodies from {}", bodies.len(), origin);
+                sync.on_bodies(origin, bodies);
+            }
+            MsgClass::EVIDENCE(evidence) => {
+                trace!("get evidence from {}", origin);
+                if let Err(err) = chain.add_evidence(evidence) {
+                    warn!("bad evidence from {}: {:?}", origin, err);
+                }
+            }
         }
     }
 }
\ No newline at end of file
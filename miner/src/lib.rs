@@ -7,11 +7,13 @@ extern crate network;
 extern crate bincode;
 extern crate parking_lot;
 extern crate tx_pool;
+#[cfg(test)]
+extern crate kvdb;
 
 use std::sync::mpsc::Sender;
 use chain::chain::Chain;
 use chain::block::Block;
-use std::thread;
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
 use util::hash::H256;
 use util::Hashable;
@@ -19,53 +21,217 @@ use std::sync::Arc;
 use network::connection::Operation;
 use util::config::SleepyConfig;
 use bincode::{serialize, Infinite};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use network::msgclass::MsgClass;
 use tx_pool::Pool;
 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MinerStatus {
+    pub paused: bool,
+    pub stopped: bool,
+    /// Time of the last proof tried, in ticks.
+    pub last_attempt: Option<u64>,
+    /// Blocks produced since the start.
+    pub blocks: u64,
+}
+
+/// Controls of the mining thread.
+pub struct MinerHandle {
+    status: Arc<Mutex<MinerStatus>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl MinerHandle {
+    pub fn pause(&self) {
+        self.status.lock().paused = true;
+        info!("mining paused");
+    }
+
+    pub fn resume(&self) {
+        self.status.lock().paused = false;
+        info!("mining resumed");
+    }
+
+    /// Stop mining and wait for the thread to exit.
+    pub fn stop(&self) {
+        self.status.lock().stopped = true;
+        if let Some(thread) = self.thread.lock().take() {
+            let _ = thread.join();
+        }
+    }
+
+    pub fn status(&self) -> MinerStatus {
+        self.status.lock().clone()
+    }
+}
+
+/// Start mining a block on each tick, paused if `paused` is set.
 pub fn start_miner(tx: Sender<(u32, Operation, Vec<u8>)>,
                    chain: Arc<Chain>,
                    config: Arc<RwLock<SleepyConfig>>,
-                   tx_pool: Arc<RwLock<Pool>>) {
-    
-    let tx = tx.clone();
-    let chain = chain.clone();
-    let config = config.clone();
-    let tx_pool = tx_pool.clone();
-    thread::spawn(move || {
+                   tx_pool: Arc<RwLock<Pool>>,
+                   paused: bool) -> MinerHandle {
+    let status = Arc::new(Mutex::new(MinerStatus { paused: paused, ..MinerStatus::default() }));
+    let shared = status.clone();
+    let thread = thread::spawn(move || {
         info!("start mining!");
-        let mut time = match {config.read().ntp_now()} {
-            Some(t) => t,
-            _ => panic!("NTP Error"),
-        };
+        let mut time: Option<u64> = None;
         loop {
-            if let Some(new_time) = {config.read().ntp_now()} {
-                if time < new_time {
-                    time = new_time;
-                    let (height, hash) = chain.get_status();
-                    let miner_privkey = {config.read().get_miner_private_key()};
-                    let anc_hash = chain.anc_hash(height, hash).unwrap();
-                    
-                    let sig = Block::gen_proof(miner_privkey, time, height + 1, anc_hash);
-                    let proof = sig.sha3();
-                    let difficulty: H256 = {config.read().get_difficulty().into()};
-
-                    if proof < difficulty {               
-                        let id = {config.read().get_id()};
-                        let (tx_list, hash_list) = { tx_pool.write().package() };
-                        let signed_blk = chain.gen_block(height, hash, time, sig, tx_list);
-                        { tx_pool.write().update(&hash_list) };
-                        info!("generate block at timestamp {}", time);
-                        let msg = MsgClass::BLOCK(signed_blk);
-                        let message = serialize(&msg, Infinite).unwrap();
-                        tx.send((id, Operation::BROADCAST, message)).unwrap();             
+            let (paused, stopped) = {
+                let status = shared.lock();
+                (status.paused, status.stopped)
+            };
+            if stopped {
+                info!("stop mining!");
+                break;
+            }
+
+            if !paused {
+                if let Some(new_time) = {config.read().now()} {
+                    if time.map_or(true, |t| t < new_time) {
+                        // the first tick seen is not mined, it may be half over
+                        if time.is_some() {
+                            let mined = mine(&tx, &chain, &config, &tx_pool, new_time);
+                            let mut status = shared.lock();
+                            status.last_attempt = Some(new_time);
+                            if mined {
+                                status.blocks += 1;
+                            }
+                        }
+                        time = Some(new_time);
                     }
+                } else {
+                    info!("time source error!!");
                 }
-            } else {
-                info!("ntp error!!");
             }
             
             thread::sleep(Duration::from_millis(100 / {config.read().nps}));
         }
     });
+
+    MinerHandle {
+        status: status,
+        thread: Mutex::new(Some(thread)),
+    }
+}
+
+/// Try to produce a block at `time` on top of the best block.
+fn mine(tx: &Sender<(u32, Operation, Vec<u8>)>,
+        chain: &Chain,
+        config: &RwLock<SleepyConfig>,
+        tx_pool: &RwLock<Pool>,
+        time: u64) -> bool {
+    let (height, hash) = chain.get_status();
+    let miner_privkey = {config.read().get_miner_private_key()};
+    // the best block may be replaced meanwhile, try again on the next tick
+    let anc_hash = match chain.anc_hash(height, hash) {
+        Some(h) => h,
+        None => {
+            warn!("no ancestor of block {} {:?}", height, hash);
+            return false;
+        }
+    };
+    
+    let sig = match Block::gen_proof(miner_privkey, time, height + 1, anc_hash) {
+        Ok(sig) => sig,
+        Err(e) => {
+            warn!("no proof for block {}: {}", height + 1, e);
+            return false;
+        }
+    };
+    let proof = sig.sha3();
+    let difficulty: H256 = match chain.difficulty(height + 1, hash) {
+        Ok(d) => d.into(),
+        Err(e) => {
+            warn!("no difficulty for block {}: {:?}", height + 1, e);
+            H256::zero()
+        }
+    };
+
+    if proof < difficulty {               
+        let id = {config.read().get_id()};
+        let (tx_list, hash_list) = { tx_pool.write().package() };
+        let signed_blk = match chain.gen_block(height, hash, time, sig, tx_list) {
+            Ok(blk) => blk,
+            Err(e) => {
+                warn!("skip block {} at timestamp {}: {:?}", height + 1, time, e);
+                return false;
+            }
+        };
+        { tx_pool.write().update(&hash_list) };
+        info!("generate block at timestamp {}", time);
+        let msg = MsgClass::BLOCK(signed_blk);
+        let message = serialize(&msg, Infinite).unwrap();
+        tx.send((id, Operation::BROADCAST, message)).unwrap();             
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use bincode::deserialize;
+    use kvdb::in_memory;
+    use chain::db;
+    use chain::chain::test_helpers::new_config;
+    use util::timesource::MockTime;
+
+    const TICK: i64 = 100000000;
+
+    /// Wait for `f` to hold, for a few seconds at most.
+    fn wait_until<F: Fn() -> bool>(f: F) -> bool {
+        for _ in 0..500 {
+            if f() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn miner_handle() {
+        let time = Arc::new(MockTime::new(1000 * TICK));
+        let config = new_config("", time.clone());
+        let chain = Chain::init(config.clone(), Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap();
+        let tx_pool = Arc::new(RwLock::new(Pool::new(10, 10)));
+        let (tx, rx) = channel();
+        let miner = start_miner(tx, chain.clone(), config, tx_pool, true);
+
+        // nothing is tried while paused
+        time.advance(10 * TICK);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(miner.status(), MinerStatus { paused: true, ..MinerStatus::default() });
+
+        // a proof is tried on each new tick until one is good enough
+        miner.resume();
+        assert!(wait_until(|| miner.status().last_attempt.is_some() || {
+            time.advance(TICK);
+            false
+        }));
+        assert!(wait_until(|| miner.status().blocks > 0 || {
+            time.advance(TICK);
+            false
+        }));
+        miner.pause();
+        // let a proof tried meanwhile finish
+        thread::sleep(Duration::from_millis(50));
+        let status = miner.status();
+        assert!(status.paused);
+        assert_eq!(chain.get_status().0, status.blocks);
+        let (_, op, message) = rx.try_recv().unwrap();
+        assert_eq!(op, Operation::BROADCAST);
+        match deserialize(&message).unwrap() {
+            MsgClass::BLOCK(block) => assert_eq!(block.height, 1),
+            _ => panic!("not a block"),
+        }
+
+        miner.stop();
+        assert!(miner.status().stopped);
+        assert!(miner.thread.lock().is_none());
+        // stopping twice is fine
+        miner.stop();
+    }
 }
@@ -5,14 +5,32 @@ use std::time::Duration;
 use std::thread;
 use std::convert::AsRef;
 use std::sync::Arc;
-use std::io::prelude::*;
+use std::io;
 use std::net::TcpStream;
 use util::config;
 use std::sync::mpsc::Receiver;
+use handshake::{NodeKeys, write_frame};
+use session::SealingSession;
 
 const TIMEOUT: u64 = 15;
 
-pub type PeerPairs = Vec<(u32, SocketAddr, Arc<RwLock<Option<TcpStream>>>)>;
+/// An authenticated stream to a peer.
+pub struct PeerStream {
+    pub stream: TcpStream,
+    pub session: Option<SealingSession>,
+}
+
+impl PeerStream {
+    /// Send a frame, sealed if the session is encrypted.
+    pub fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        match self.session {
+            Some(ref mut session) => write_frame(&mut self.stream, &session.seal(payload)),
+            None => write_frame(&mut self.stream, payload),
+        }
+    }
+}
+
+pub type PeerPairs = Vec<(u32, SocketAddr, Arc<RwLock<Option<PeerStream>>>)>;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Operation {
@@ -26,6 +44,7 @@ impl ::std::marker::Copy for Operation {}
 pub struct Connection {
     pub id_card: u32,
     pub peers_pair: PeerPairs,
+    pub keys: Arc<NodeKeys>,
 }
 
 impl Connection {
@@ -42,30 +61,46 @@ impl Connection {
         Connection {
             id_card,
             peers_pair,
+            keys: Arc::new(NodeKeys::from_config(config)),
         }
     }
 }
 
 pub fn do_connect(con: &Connection) {
-    for &(_, addr, ref stream) in &con.peers_pair {
+    for &(id_card, addr, ref stream) in &con.peers_pair {
         let stream_lock = stream.clone();
+        let keys = con.keys.clone();
         thread::spawn(move || loop {
                           {
                               let stream_opt = &mut *stream_lock.as_ref().write();
                               if stream_opt.is_none() {
                                   trace!("connet {:?}", addr);
                                   let stream = TcpStream::connect(addr).ok();
-                                  *stream_opt = stream;
+                                  *stream_opt = stream.and_then(|mut stream| {
+                                      let _ = stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT)));
+                                      match keys.initiate(&mut stream, id_card) {
+                                          Ok(key) => {
+                                              info!("authenticated to peer {}, encrypted {}", id_card, key.is_some());
+                                              let _ = stream.set_read_timeout(None);
+                                              Some(PeerStream {
+                                                       stream: stream,
+                                                       session: key.map(|k| SealingSession::new(&k)),
+                                                   })
+                                          }
+                                          Err(err) => {
+                                              warn!("handshake with {:?} error {:?}", addr, err);
+                                              None
+                                          }
+                                      }
+                                  });
                               }
 
                               let mut need_reconnect = false;
-                              if let Some(ref mut stream) = stream_opt.as_mut() {
-                                  trace!("handshake with {:?}!", addr);
-                                  let mut header = [0; 8];
-                                  BigEndian::write_u64(&mut header, 0xDEADBEEF00000000 as u64);
-                                  let res = stream.write(&header);
+                              if let Some(ref mut peer) = stream_opt.as_mut() {
+                                  trace!("keep alive {:?}!", addr);
+                                  let res = write_frame(&mut peer.stream, &[]);
                                   if res.is_err() {
-                                      warn!("handshake with {:?} error!", addr);
+                                      warn!("keep alive {:?} error!", addr);
                                       need_reconnect = true;
                                   }
                               }
@@ -83,20 +118,16 @@ pub fn do_connect(con: &Connection) {
 }
 
 pub fn broadcast(con: &Connection, msg: Vec<u8>, origin: u32, operate: Operation) {
-    let request_id = 0xDEADBEEF00000000 + msg.len() + 4;
-    let mut encoded_request_id = [0; 8];
-    BigEndian::write_u64(&mut encoded_request_id, request_id as u64);
     let mut encoded_origin = [0; 4];
     BigEndian::write_u32(&mut encoded_origin, con.id_card);
     let mut buf = Vec::new();
-    buf.extend(&encoded_request_id);
     buf.extend(&encoded_origin);
     buf.extend(msg);
-    let send_msg = move |stream: &Arc<RwLock<Option<TcpStream>>>| {
+    let send_msg = move |stream: &Arc<RwLock<Option<PeerStream>>>| {
         let streams_lock = stream.clone();
         let stream_opt = &mut (*streams_lock.as_ref().write());
-        if let Some(ref mut stream) = stream_opt.as_mut() {
-            let _ = stream.write(&buf);
+        if let Some(ref mut peer) = stream_opt.as_mut() {
+            let _ = peer.send(&buf);
         }
     };
     let mut peers = vec![];
@@ -0,0 +1,373 @@
+//! Handshake authenticating peers by their signer keys.
+//!
+//! The node which dials (initiator) and the node which accepts (responder)
+//! each sign both nonces with their `signer_private_key`:
+//!
+//! ```text
+//! initiator -> responder: Hello { id, nonce, ephemeral, encrypt }
+//! responder -> initiator: Challenge { body: { id, nonce, ephemeral, encrypt }, signature }
+//! initiator -> responder: Auth { signature }
+//! responder -> initiator: [1]
+//! ```
+//!
+//! A peer is accepted only if it signs with the `signer_public_key` configured
+//! for its `id_card`, and that key is one of the `keygroups`. Observer peers
+//! sign with a key of their own which need not be one of the `keygroups`.
+//!
+//! The session is encrypted if either side asks for it. The key is agreed by
+//! ECDH over the signed ephemeral keys, see `session`.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use byteorder::{BigEndian, ByteOrder};
+use bincode::{serialize, deserialize, Infinite};
+use util::{H256, H512, H520, Hashable};
+use util::config::SleepyConfig;
+use crypto::{sign, recover, agree, Signature, KeyPair};
+use session::session_key;
+
+/// Handshake message header, same framing as the server protocol.
+const FRAME_FLAG: u64 = 0xDEADBEEF00000000;
+/// Max size of a handshake frame.
+const MAX_FRAME: u64 = 1024;
+
+const INITIATOR: u8 = 0;
+const RESPONDER: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hello {
+    pub id: u32,
+    pub nonce: H256,
+    pub ephemeral: H512,
+    pub encrypt: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChallengeBody {
+    pub id: u32,
+    pub nonce: H256,
+    pub ephemeral: H512,
+    /// Whether the session is encrypted.
+    pub encrypt: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Challenge {
+    pub body: ChallengeBody,
+    pub signature: H520,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Auth {
+    pub signature: H520,
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Io(io::Error),
+    InvalidFormat,
+    UnknownPeer,
+    InvalidSignature,
+    /// The peer does not encrypt the session though we ask for it.
+    Unencrypted,
+    Rejected,
+}
+
+impl From<io::Error> for HandshakeError {
+    fn from(err: io::Error) -> Self {
+        HandshakeError::Io(err)
+    }
+}
+
+/// Handshake state of an accepted connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerState {
+    New,
+    /// Waiting for `Auth` from the initiator: its hello, our challenge and our
+    /// ephemeral secret.
+    Challenged(Hello, ChallengeBody, H256),
+    /// The id of the peer and the session key if the session is encrypted.
+    Authenticated(u32, Option<H256>),
+}
+
+/// The hash signed by `role`.
+fn auth_hash(role: u8, hello: &Hello, body: &ChallengeBody) -> H256 {
+    let mut data = vec![role];
+    data.extend(serialize(hello, Infinite).unwrap());
+    data.extend(serialize(body, Infinite).unwrap());
+    data.sha3()
+}
+
+pub fn write_frame<W: Write>(stream: &mut W, payload: &[u8]) -> io::Result<()> {
+    let mut header = [0; 8];
+    BigEndian::write_u64(&mut header, FRAME_FLAG + payload.len() as u64);
+    stream.write_all(&header)?;
+    stream.write_all(payload)
+}
+
+pub fn read_frame<R: Read>(stream: &mut R) -> Result<Vec<u8>, HandshakeError> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let flag = BigEndian::read_u64(&header);
+    if flag & 0xffffffff00000000 != FRAME_FLAG || flag & 0xffffffff > MAX_FRAME {
+        return Err(HandshakeError::InvalidFormat);
+    }
+    let mut payload = vec![0; (flag & 0xffffffff) as usize];
+    stream.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Our signer key and the signer keys of the peers.
+pub struct NodeKeys {
+    pub id: u32,
+    private_key: H256,
+    peers: HashMap<u32, H512>,
+    /// Ask peers to encrypt the session.
+    encrypt: bool,
+}
+
+impl NodeKeys {
+    pub fn new(id: u32, private_key: H256, peers: HashMap<u32, H512>, encrypt: bool) -> Self {
+        NodeKeys {
+            id: id,
+            private_key: private_key,
+            peers: peers,
+            encrypt: encrypt,
+        }
+    }
+
+    pub fn from_config(config: &SleepyConfig) -> Self {
+        let peers = config.peers
+            .iter()
+            .filter(|p| {
+                let known = p.observer || config.public_keys.contains_key(&p.signer_public_key);
+                if !known {
+                    warn!("signer key of peer {} is not in keygroups", p.id_card);
+                }
+                known
+            })
+            .map(|p| (p.id_card, p.signer_public_key))
+            .collect();
+        NodeKeys::new(config.get_id(), config.get_signer_private_key(), peers, config.encryption)
+    }
+
+    fn sign(&self, hash: &H256) -> H520 {
+        sign(&self.private_key, hash).unwrap().into()
+    }
+
+    fn verify(&self, peer: u32, hash: &H256, signature: &H520) -> Result<(), HandshakeError> {
+        let expected = self.peers.get(&peer).ok_or(HandshakeError::UnknownPeer)?;
+        let signature: Signature = signature.clone().into();
+        match recover(&signature, hash) {
+            Ok(ref public) if public == expected => Ok(()),
+            _ => Err(HandshakeError::InvalidSignature),
+        }
+    }
+
+    /// Our hello and the ephemeral key pair it announces.
+    pub fn hello(&self) -> (Hello, KeyPair) {
+        let ephemeral = KeyPair::random();
+        let hello = Hello {
+            id: self.id,
+            nonce: H256::random(),
+            ephemeral: *ephemeral.pubkey(),
+            encrypt: self.encrypt,
+        };
+        (hello, ephemeral)
+    }
+
+    /// Check the challenge of the dialed `peer` and answer it.
+    /// Returns the session key if the session is encrypted.
+    pub fn answer(&self, peer: u32, hello: &Hello, ephemeral: &KeyPair, challenge: &Challenge) -> Result<(Auth, Option<H256>), HandshakeError> {
+        let body = &challenge.body;
+        if body.id != peer {
+            return Err(HandshakeError::UnknownPeer);
+        }
+        self.verify(peer, &auth_hash(RESPONDER, hello, body), &challenge.signature)?;
+        if hello.encrypt && !body.encrypt {
+            return Err(HandshakeError::Unencrypted);
+        }
+        let key = if body.encrypt {
+            let shared = agree(ephemeral.privkey(), &body.ephemeral).map_err(|_| HandshakeError::InvalidFormat)?;
+            Some(session_key(&shared, &hello.nonce, &body.nonce))
+        } else {
+            None
+        };
+        let auth = Auth { signature: self.sign(&auth_hash(INITIATOR, hello, body)) };
+        Ok((auth, key))
+    }
+
+    /// Authenticate to `peer` over a freshly connected stream.
+    /// Returns the session key if the session is encrypted.
+    pub fn initiate<S: Read + Write>(&self, stream: &mut S, peer: u32) -> Result<Option<H256>, HandshakeError> {
+        let (hello, ephemeral) = self.hello();
+        write_frame(stream, &serialize(&hello, Infinite).unwrap())?;
+        let challenge: Challenge = deserialize(&read_frame(stream)?).map_err(|_| HandshakeError::InvalidFormat)?;
+        let (auth, key) = self.answer(peer, &hello, &ephemeral, &challenge)?;
+        write_frame(stream, &serialize(&auth, Infinite).unwrap())?;
+        if read_frame(stream)? == vec![1] {
+            Ok(key)
+        } else {
+            Err(HandshakeError::Rejected)
+        }
+    }
+
+    /// Handle a handshake frame of an accepted connection and return the reply.
+    pub fn respond(&self, state: &mut PeerState, payload: &[u8]) -> Result<Vec<u8>, HandshakeError> {
+        match state.clone() {
+            PeerState::New => {
+                let hello: Hello = deserialize(payload).map_err(|_| HandshakeError::InvalidFormat)?;
+                if !self.peers.contains_key(&hello.id) {
+                    return Err(HandshakeError::UnknownPeer);
+                }
+                let ephemeral = KeyPair::random();
+                let body = ChallengeBody {
+                    id: self.id,
+                    nonce: H256::random(),
+                    ephemeral: *ephemeral.pubkey(),
+                    encrypt: hello.encrypt || self.encrypt,
+                };
+                let challenge = Challenge {
+                    signature: self.sign(&auth_hash(RESPONDER, &hello, &body)),
+                    body: body.clone(),
+                };
+                *state = PeerState::Challenged(hello, body, *ephemeral.privkey());
+                Ok(serialize(&challenge, Infinite).unwrap())
+            }
+            PeerState::Challenged(hello, body, secret) => {
+                *state = PeerState::New;
+                let auth: Auth = deserialize(payload).map_err(|_| HandshakeError::InvalidFormat)?;
+                self.verify(hello.id, &auth_hash(INITIATOR, &hello, &body), &auth.signature)?;
+                let key = if body.encrypt {
+                    let shared = agree(&secret, &hello.ephemeral).map_err(|_| HandshakeError::InvalidFormat)?;
+                    Some(session_key(&shared, &hello.nonce, &body.nonce))
+                } else {
+                    None
+                };
+                *state = PeerState::Authenticated(hello.id, key);
+                Ok(vec![1])
+            }
+            PeerState::Authenticated(..) => Err(HandshakeError::InvalidFormat),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(encrypt0: bool, encrypt1: bool) -> (NodeKeys, NodeKeys, H256) {
+        let k0 = H256::from("40f2d8f8e1594579824fd04edfc7ff1ddffd6be153b23f4318e1acff037d3ea9");
+        let k1 = H256::from("9c70d613d88686c29d4af9b77dbbc290ca4a71124019406e582bde9338d3e6a8");
+        let k2 = H256::from("5a39ed1020c04d4d84539975b893a4e7c53eab6c2965db8bc3468093a31bc5ae");
+        let p0 = *KeyPair::from_privkey(k0).unwrap().pubkey();
+        let p1 = *KeyPair::from_privkey(k1).unwrap().pubkey();
+        let node0 = NodeKeys::new(0, k0, vec![(1, p1)].into_iter().collect(), encrypt0);
+        let node1 = NodeKeys::new(1, k1, vec![(0, p0)].into_iter().collect(), encrypt1);
+        (node0, node1, k2)
+    }
+
+    /// Run a handshake from `initiator` to node 1 and return both session keys.
+    fn run(initiator: &NodeKeys, responder: &NodeKeys) -> (Option<H256>, Option<H256>) {
+        let mut state = PeerState::New;
+        let (hello, ephemeral) = initiator.hello();
+        let reply = responder.respond(&mut state, &serialize(&hello, Infinite).unwrap()).unwrap();
+        let challenge: Challenge = deserialize(&reply).unwrap();
+        let (auth, key) = initiator.answer(1, &hello, &ephemeral, &challenge).unwrap();
+        let reply = responder.respond(&mut state, &serialize(&auth, Infinite).unwrap()).unwrap();
+        assert_eq!(reply, vec![1]);
+        match state {
+            PeerState::Authenticated(id, responder_key) => {
+                assert_eq!(id, initiator.id);
+                (key, responder_key)
+            }
+            s => panic!("unexpected {:?}", s),
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let (node0, node1, _) = keys(false, false);
+        assert_eq!(run(&node0, &node1), (None, None));
+
+        // the challenge can't be answered as another peer
+        let mut state = PeerState::New;
+        let (hello, ephemeral) = node0.hello();
+        let reply = node1.respond(&mut state, &serialize(&hello, Infinite).unwrap()).unwrap();
+        let challenge: Challenge = deserialize(&reply).unwrap();
+        assert!(node0.answer(2, &hello, &ephemeral, &challenge).is_err());
+    }
+
+    #[test]
+    fn encrypted_handshake() {
+        for &(encrypt0, encrypt1) in &[(true, false), (false, true), (true, true)] {
+            let (node0, node1, _) = keys(encrypt0, encrypt1);
+            let (k0, k1) = run(&node0, &node1);
+            assert!(k0.is_some());
+            assert_eq!(k0, k1);
+        }
+    }
+
+    #[test]
+    fn downgrade() {
+        let (node0, node1, _) = keys(true, false);
+        let mut state = PeerState::New;
+        let (hello, ephemeral) = node0.hello();
+        let reply = node1.respond(&mut state, &serialize(&hello, Infinite).unwrap()).unwrap();
+        let mut challenge: Challenge = deserialize(&reply).unwrap();
+        challenge.body.encrypt = false;
+        match node0.answer(1, &hello, &ephemeral, &challenge) {
+            Err(HandshakeError::InvalidSignature) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn impersonation() {
+        let (node0, node1, k2) = keys(false, false);
+        // a node with another key claiming to be node 0
+        let fake = NodeKeys::new(0, k2, node0.peers.clone(), false);
+        let mut state = PeerState::New;
+        let (hello, ephemeral) = fake.hello();
+        let reply = node1.respond(&mut state, &serialize(&hello, Infinite).unwrap()).unwrap();
+        let challenge: Challenge = deserialize(&reply).unwrap();
+        let (auth, _) = fake.answer(1, &hello, &ephemeral, &challenge).unwrap();
+        match node1.respond(&mut state, &serialize(&auth, Infinite).unwrap()) {
+            Err(HandshakeError::InvalidSignature) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(state, PeerState::New);
+
+        // a node which is not configured
+        let stranger = NodeKeys::new(5, k2, HashMap::new(), false);
+        let (hello, _) = stranger.hello();
+        match node1.respond(&mut state, &serialize(&hello, Infinite).unwrap()) {
+            Err(HandshakeError::UnknownPeer) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn observer() {
+        let (node0, node1, k2) = keys(false, false);
+        let k3 = H256::from("9c70d613d88686c29d4af9b77dbbc290ca4a71124019406e582bde9338d3e6a9");
+        let p2 = *KeyPair::from_privkey(k2).unwrap().pubkey();
+        // node 1 knows the key of observer 0, which is not one of the keygroups
+        let node1 = NodeKeys::new(1, node1.private_key, vec![(0, p2)].into_iter().collect(), false);
+        let observer = NodeKeys::new(0, k2, node0.peers.clone(), false);
+        assert_eq!(run(&observer, &node1), (None, None));
+
+        // an observer signing with another key is refused
+        let fake = NodeKeys::new(0, k3, node0.peers.clone(), false);
+        let mut state = PeerState::New;
+        let (hello, ephemeral) = fake.hello();
+        let reply = node1.respond(&mut state, &serialize(&hello, Infinite).unwrap()).unwrap();
+        let challenge: Challenge = deserialize(&reply).unwrap();
+        let (auth, _) = fake.answer(1, &hello, &ephemeral, &challenge).unwrap();
+        match node1.respond(&mut state, &serialize(&auth, Infinite).unwrap()) {
+            Err(HandshakeError::InvalidSignature) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        assert_eq!(state, PeerState::New);
+    }
+}
@@ -12,6 +12,9 @@ extern crate parking_lot;
 extern crate util;
 extern crate serde;
 extern crate chain;
+extern crate crypto;
+extern crate bincode;
+extern crate ring;
 #[macro_use]
 extern crate serde_derive;
 
@@ -19,4 +22,6 @@ pub mod server;
 pub mod connection;
 pub mod protocol;
 pub mod msgclass;
-pub mod msghandle;
\ No newline at end of file
+pub mod msghandle;
+pub mod handshake;
+pub mod session;
\ No newline at end of file
@@ -1,5 +1,6 @@
-use chain::block::Block;
+use chain::block::{Block, Header, Body};
 use chain::transaction::SignedTransaction;
+use chain::evidence::Evidence;
 use util::hash::H256;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -8,4 +9,14 @@ pub enum MsgClass {
     SYNCREQ(H256),
     TX(SignedTransaction),
     MSG(Vec<u8>),
+    /// best height and hash of the sender
+    STATUS(u64, H256),
+    /// request `count` canonical headers starting at the given height
+    GETHEADERS(u64, u64),
+    HEADERS(Vec<Header>),
+    /// request the bodies of the given blocks
+    GETBODIES(Vec<H256>),
+    BODIES(Vec<(H256, Body)>),
+    /// a validator signed two blocks for one slot
+    EVIDENCE(Evidence),
 }
\ No newline at end of file
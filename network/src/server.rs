@@ -1,6 +1,8 @@
 use std::net::SocketAddr;
 use std::{io, thread};
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
+use parking_lot::Mutex;
 
 use futures::Future;
 use futures::future::result;
@@ -10,6 +12,8 @@ use tokio_service::Service;
 use util::config::SleepyConfig;
 use protocol::{SleepyProto, SleepyRequest, SleepyResponse};
 use msghandle::net_msg_handler;
+use handshake::{NodeKeys, PeerState};
+use session::OpeningSession;
 
 #[derive(Clone)]
 pub struct MySender {
@@ -30,6 +34,50 @@ unsafe impl Sync for MySender {}
 
 struct Server {
     mysender: MySender,
+    keys: Arc<NodeKeys>,
+    state: Mutex<PeerState>,
+    session: Mutex<Option<OpeningSession>>,
+}
+
+impl Server {
+    fn handle(&self, req: SleepyRequest) -> Result<SleepyResponse, io::Error> {
+        // empty frames keep the connection alive
+        if req.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut state = self.state.lock();
+        let authenticated = match *state {
+            PeerState::Authenticated(origin, _) => Some(origin),
+            _ => None,
+        };
+        if let Some(origin) = authenticated {
+            let req = match self.session.lock().as_mut() {
+                Some(session) => session.open(req),
+                None => Some(req),
+            };
+            return match req {
+                Some(req) => net_msg_handler(req, origin, &self.mysender),
+                None => {
+                    warn!("drop connection of peer {}, frame was modified", origin);
+                    *state = PeerState::New;
+                    Err(io::Error::new(io::ErrorKind::InvalidData, "invalid frame"))
+                }
+            };
+        }
+        match self.keys.respond(&mut state, &req) {
+            Ok(reply) => {
+                if let PeerState::Authenticated(origin, key) = *state {
+                    info!("peer {} authenticated, encrypted {}", origin, key.is_some());
+                    *self.session.lock() = key.map(|k| OpeningSession::new(&k));
+                }
+                Ok(reply)
+            }
+            Err(err) => {
+                warn!("handshake error {:?}", err);
+                Ok(vec![])
+            }
+        }
+    }
 }
 
 impl Service for Server {
@@ -39,18 +87,26 @@ impl Service for Server {
     type Future = Box<Future<Item = Self::Response, Error = io::Error>>;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        Box::new(result(net_msg_handler(req, &self.mysender)))
+        Box::new(result(self.handle(req)))
     }
 }
 
 pub fn start_server(config: &SleepyConfig, tx: Sender<(u32, SleepyRequest)>) {
     let mysender = MySender::new(tx);
+    let keys = Arc::new(NodeKeys::from_config(config));
     let addr = format!("0.0.0.0:{}", config.port);
     let addr = addr.parse::<SocketAddr>().unwrap();
 
     thread::spawn(move || {
                       info!("start server on {:?}!", addr);
                       TcpServer::new(SleepyProto, addr)
-                          .serve(move || Ok(Server { mysender: mysender.clone() }));
+                          .serve(move || {
+                                     Ok(Server {
+                                            mysender: mysender.clone(),
+                                            keys: keys.clone(),
+                                            state: Mutex::new(PeerState::New),
+                                            session: Mutex::new(None),
+                                        })
+                                 });
                   });
 }
@@ -0,0 +1,101 @@
+//! Encrypted session of an authenticated connection.
+//!
+//! Frame payloads are sealed with ChaCha20-Poly1305. The nonce is the number
+//! of frames sent before, so frames which are dropped, replayed or reordered
+//! fail to open.
+
+use byteorder::{BigEndian, ByteOrder};
+use ring::aead::{self, SealingKey, OpeningKey, CHACHA20_POLY1305};
+use util::{H256, Hashable};
+
+const TAG_LEN: usize = 16;
+
+/// Derive the session key from the ECDH secret and the handshake nonces.
+pub fn session_key(shared: &H256, initiator_nonce: &H256, responder_nonce: &H256) -> H256 {
+    let mut data = shared.to_vec();
+    data.extend_from_slice(initiator_nonce);
+    data.extend_from_slice(responder_nonce);
+    data.sha3()
+}
+
+fn nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    BigEndian::write_u64(&mut nonce[4..], counter);
+    nonce
+}
+
+/// Sending side of a session.
+pub struct SealingSession {
+    key: SealingKey,
+    counter: u64,
+}
+
+impl SealingSession {
+    pub fn new(key: &H256) -> Self {
+        SealingSession {
+            key: SealingKey::new(&CHACHA20_POLY1305, key).unwrap(),
+            counter: 0,
+        }
+    }
+
+    pub fn seal(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut buf = payload.to_vec();
+        buf.extend_from_slice(&[0; TAG_LEN]);
+        let len = aead::seal_in_place(&self.key, &nonce(self.counter), &[], &mut buf, TAG_LEN).unwrap();
+        buf.truncate(len);
+        self.counter += 1;
+        buf
+    }
+}
+
+/// Receiving side of a session.
+pub struct OpeningSession {
+    key: OpeningKey,
+    counter: u64,
+}
+
+impl OpeningSession {
+    pub fn new(key: &H256) -> Self {
+        OpeningSession {
+            key: OpeningKey::new(&CHACHA20_POLY1305, key).unwrap(),
+            counter: 0,
+        }
+    }
+
+    /// Decrypt a frame, `None` if it was modified.
+    pub fn open(&mut self, mut payload: Vec<u8>) -> Option<Vec<u8>> {
+        let len = match aead::open_in_place(&self.key, &nonce(self.counter), &[], 0, &mut payload) {
+            Ok(plain) => plain.len(),
+            Err(_) => return None,
+        };
+        payload.truncate(len);
+        self.counter += 1;
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let key = session_key(&H256::from(1), &H256::from(2), &H256::from(3));
+        let mut sealing = SealingSession::new(&key);
+        let mut opening = OpeningSession::new(&key);
+
+        let first = sealing.seal(b"block");
+        assert!(first.len() == 5 + TAG_LEN);
+        assert!(&first[..5] != b"block");
+        assert_eq!(opening.open(first.clone()), Some(b"block".to_vec()));
+        // replay
+        assert_eq!(opening.open(first), None);
+
+        let mut second = sealing.seal(b"tx");
+        second[0] ^= 1;
+        assert_eq!(opening.open(second), None);
+
+        let mut other = OpeningSession::new(&H256::from(4));
+        assert_eq!(other.open(SealingSession::new(&key).seal(b"tx")), None);
+    }
+}
@@ -0,0 +1,288 @@
+//! HTTP JSON-RPC endpoint for querying the chain and submitting transactions.
+//!
+//! Methods:
+//!
+//! - `getStatus` → `{"height", "hash"}` of the best block
+//! - `getFinalized` → `{"height", "hash"}` of the last final block
+//! - `getBlockByHash(hash)` → block or `null`
+//! - `getBlockHashByNumber(height)` → hash or `null`
+//! - `getTransactionAddress(hash)` → `{"block_hash", "index"}` or `null`
+//! - `getTransactionConfirmations(hash)` → number of blocks from the one
+//!   including the transaction to the best block, or `null`
+//! - `getPoolSize` → number of pending transactions
+//! - `sendRawTransaction(hex)` → hash of the accepted transaction, which is
+//!   given as hex encoded rlp of a `SignedTransaction`
+//! - `getMinerStatus` → `{"paused", "stopped", "last_attempt", "blocks"}`
+//! - `pauseMining`, `resumeMining` → `true`
+//!
+//! The mining methods fail on an observer node.
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate bincode;
+#[macro_use]
+extern crate log;
+extern crate parking_lot;
+extern crate rustc_hex;
+extern crate util;
+extern crate chain;
+extern crate network;
+extern crate tx_pool;
+extern crate miner;
+extern crate rlp;
+
+pub mod types;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use parking_lot::{Mutex, RwLock};
+use serde_json::Value;
+use rustc_hex::FromHex;
+use bincode::{serialize, Infinite};
+use rlp::UntrustedRlp;
+use util::H256;
+use chain::chain::Chain;
+use chain::transaction::SignedTransaction;
+use network::connection::Operation;
+use network::msgclass::MsgClass;
+use tx_pool::Pool;
+use miner::MinerHandle;
+use types::{Request, Response, RpcError};
+
+/// Max size of a request body.
+const MAX_BODY: usize = 1 << 20;
+/// A client silent for longer than this, reading or writing, is dropped.
+const IO_TIMEOUT_SECS: u64 = 10;
+
+pub struct RpcHandler {
+    id: u32,
+    chain: Arc<Chain>,
+    tx_pool: Arc<RwLock<Pool>>,
+    /// `None` on an observer.
+    miner: Option<Arc<MinerHandle>>,
+    tx: Mutex<Sender<(u32, Operation, Vec<u8>)>>,
+}
+
+fn to_value<T: serde::Serialize>(v: T) -> Result<Value, RpcError> {
+    serde_json::to_value(v).map_err(|e| RpcError::server(format!("{}", e)))
+}
+
+impl RpcHandler {
+    pub fn new(tx: Sender<(u32, Operation, Vec<u8>)>, chain: Arc<Chain>, tx_pool: Arc<RwLock<Pool>>, miner: Option<Arc<MinerHandle>>, id: u32) -> Self {
+        RpcHandler {
+            id: id,
+            chain: chain,
+            tx_pool: tx_pool,
+            miner: miner,
+            tx: Mutex::new(tx),
+        }
+    }
+
+    /// Handle a request body and return the response body.
+    pub fn handle_raw(&self, body: &[u8]) -> String {
+        let response = match serde_json::from_slice::<Value>(body) {
+            Err(_) => Response::new(Value::Null, Err(RpcError::parse_error())),
+            Ok(value) => match serde_json::from_value::<Request>(value) {
+                Err(_) => Response::new(Value::Null, Err(RpcError::invalid_request())),
+                Ok(req) => self.handle(req),
+            },
+        };
+        serde_json::to_string(&response).unwrap()
+    }
+
+    pub fn handle(&self, req: Request) -> Response {
+        let result = self.call(&req);
+        Response::new(req.id, result)
+    }
+
+    fn call(&self, req: &Request) -> Result<Value, RpcError> {
+        trace!("rpc call {} {:?}", req.method, req.params);
+        match req.method.as_str() {
+            "getStatus" => {
+                let (height, hash) = self.chain.get_status();
+                Ok(Value::Object(json_object(vec![("height", to_value(height)?), ("hash", to_value(hash)?)])))
+            }
+            "getFinalized" => {
+                let (height, hash) = self.chain.finalized();
+                Ok(Value::Object(json_object(vec![("height", to_value(height)?), ("hash", to_value(hash)?)])))
+            }
+            "getBlockByHash" => {
+                let hash: H256 = req.param(0)?;
+                match self.chain.get_block_by_hash(&hash) {
+                    Some(block) => {
+                        let mut value = to_value(&block)?;
+                        if let Value::Object(ref mut map) = value {
+                            map.insert("hash".to_owned(), to_value(block.hash())?);
+                        }
+                        Ok(value)
+                    }
+                    None => Ok(Value::Null),
+                }
+            }
+            "getBlockHashByNumber" => {
+                let height: u64 = req.param(0)?;
+                if height > self.chain.current_height() {
+                    return Ok(Value::Null);
+                }
+                to_value(self.chain.block_hash_by_number(height))
+            }
+            "getTransactionAddress" => {
+                let hash: H256 = req.param(0)?;
+                match self.chain.get_transaction_address(&hash) {
+                    Some(address) => Ok(Value::Object(json_object(vec![("block_hash", to_value(address.block_hash)?), ("index", to_value(address.index)?)]))),
+                    None => Ok(Value::Null),
+                }
+            }
+            "getTransactionConfirmations" => {
+                let hash: H256 = req.param(0)?;
+                to_value(self.chain.transaction_confirmations(&hash))
+            }
+            "getPoolSize" => to_value(self.tx_pool.read().len()),
+            "getMinerStatus" => {
+                let status = self.miner()?.status();
+                Ok(Value::Object(json_object(vec![
+                    ("paused", to_value(status.paused)?),
+                    ("stopped", to_value(status.stopped)?),
+                    ("last_attempt", to_value(status.last_attempt)?),
+                    ("blocks", to_value(status.blocks)?),
+                ])))
+            }
+            "pauseMining" => {
+                self.miner()?.pause();
+                Ok(Value::Bool(true))
+            }
+            "resumeMining" => {
+                self.miner()?.resume();
+                Ok(Value::Bool(true))
+            }
+            "sendRawTransaction" => {
+                let raw: String = req.param(0)?;
+                let stx = decode_transaction(&raw)?;
+                let hash = self.send_transaction(stx)?;
+                to_value(hash)
+            }
+            method => Err(RpcError::method_not_found(method)),
+        }
+    }
+
+    fn miner(&self) -> Result<&MinerHandle, RpcError> {
+        match self.miner {
+            Some(ref miner) => Ok(&**miner),
+            None => Err(RpcError::server("not mining, the node is an observer".to_owned())),
+        }
+    }
+
+    fn send_transaction(&self, stx: SignedTransaction) -> Result<H256, RpcError> {
+        self.chain.tx_basic_check(&stx).map_err(|e| RpcError::server(format!("invalid transaction: {:?}", e)))?;
+        let hash = stx.hash();
+        self.tx_pool.write().enqueue(stx.clone(), hash).map_err(|e| RpcError::server(e.to_string()))?;
+        let message = serialize(&MsgClass::TX(stx), Infinite).unwrap();
+        self.tx.lock().send((self.id, Operation::BROADCAST, message)).unwrap();
+        Ok(hash)
+    }
+}
+
+fn json_object(fields: Vec<(&str, Value)>) -> serde_json::Map<String, Value> {
+    fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect()
+}
+
+/// Decode a hex encoded rlp `SignedTransaction` and check its hash.
+pub fn decode_transaction(raw: &str) -> Result<SignedTransaction, RpcError> {
+    let raw = if raw.starts_with("0x") { &raw[2..] } else { raw };
+    let bytes: Vec<u8> = raw.from_hex().map_err(|_| RpcError::invalid_params("invalid hex".to_owned()))?;
+    let stx: SignedTransaction = UntrustedRlp::new(&bytes).as_val().map_err(|_| RpcError::invalid_params("invalid transaction rlp".to_owned()))?;
+    if stx.hash() != stx.cal_hash() {
+        return Err(RpcError::invalid_params("transaction hash mismatch".to_owned()));
+    }
+    Ok(stx)
+}
+
+fn handle_connection(handler: &RpcHandler, stream: TcpStream) -> Result<(), ::std::io::Error> {
+    // each connection holds a thread, don't let an idle one hold it forever
+    stream.set_read_timeout(Some(Duration::from_secs(IO_TIMEOUT_SECS)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(IO_TIMEOUT_SECS)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        if name == "content-length" {
+            content_length = parts.next().unwrap_or("").trim().parse().unwrap_or(0);
+        }
+    }
+
+    let (status, body) = if !request_line.starts_with("POST ") {
+        ("405 Method Not Allowed", String::new())
+    } else if content_length > MAX_BODY {
+        ("413 Payload Too Large", String::new())
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        ("200 OK", handler.handle_raw(&body))
+    };
+
+    let mut stream = stream;
+    write!(stream,
+           "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status,
+           body.len(),
+           body)?;
+    stream.flush()
+}
+
+/// Serve JSON-RPC over HTTP on localhost at `port`.
+pub fn start_rpc(port: u64, handler: Arc<RpcHandler>) {
+    let addr = format!("127.0.0.1:{}", port);
+    let listener = TcpListener::bind(&addr[..]).unwrap();
+    info!("start rpc server on {}!", addr);
+    thread::spawn(move || for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let handler = handler.clone();
+                thread::spawn(move || if let Err(e) = handle_connection(&handler, stream) {
+                    warn!("rpc connection error {:?}", e);
+                });
+            }
+            Err(e) => warn!("rpc accept error {:?}", e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hex::ToHex;
+    use chain::transaction::Transaction;
+
+    #[test]
+    fn raw_transaction() {
+        let private_key = H256::from("40f2d8f8e1594579824fd04edfc7ff1ddffd6be153b23f4318e1acff037d3ea9");
+        let stx = Transaction::new(1).sign(&private_key);
+        let raw = rlp::encode(&stx).to_vec().to_hex();
+        assert_eq!(decode_transaction(&raw), Ok(stx.clone()));
+        assert_eq!(decode_transaction(&format!("0x{}", raw)), Ok(stx.clone()));
+        assert!(decode_transaction("0xzz").is_err());
+        assert!(decode_transaction("c0").is_err());
+
+        let mut bad = stx.clone();
+        bad.hash = H256::from(1);
+        assert!(decode_transaction(&rlp::encode(&bad).to_vec().to_hex()).is_err());
+    }
+}
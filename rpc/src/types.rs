@@ -0,0 +1,104 @@
+//! JSON-RPC 2.0 request and response objects.
+
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    pub jsonrpc: Option<String>,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+}
+
+impl Request {
+    /// Decode the positional parameter `index`.
+    pub fn param<T: DeserializeOwned>(&self, index: usize) -> Result<T, RpcError> {
+        let value = self.params.get(index).cloned().ok_or_else(|| RpcError::invalid_params(format!("missing parameter {}", index)))?;
+        serde_json::from_value(value).map_err(|e| RpcError::invalid_params(format!("parameter {}: {}", index, e)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn parse_error() -> Self {
+        RpcError { code: -32700, message: "Parse error".to_owned() }
+    }
+
+    pub fn invalid_request() -> Self {
+        RpcError { code: -32600, message: "Invalid request".to_owned() }
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        RpcError { code: -32601, message: format!("Method not found: {}", method) }
+    }
+
+    pub fn invalid_params(message: String) -> Self {
+        RpcError { code: -32602, message: message }
+    }
+
+    /// An error while serving a valid request, e.g. a rejected transaction.
+    pub fn server(message: String) -> Self {
+        RpcError { code: -32000, message: message }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(v) => (Some(v), None),
+            Err(e) => (None, Some(e)),
+        };
+        Response {
+            jsonrpc: "2.0".to_owned(),
+            id: id,
+            result: result,
+            error: error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::H256;
+
+    #[test]
+    fn request_params() {
+        let req: Request = serde_json::from_str(r#"{"jsonrpc":"2.0","id":1,"method":"getBlockHashByNumber","params":[3, "0x0000000000000000000000000000000000000000000000000000000000000001"]}"#).unwrap();
+        assert_eq!(req.id, Value::from(1));
+        assert_eq!(req.param::<u64>(0), Ok(3));
+        assert_eq!(req.param::<H256>(1), Ok(H256::from(1)));
+        assert_eq!(req.param::<u64>(1).unwrap_err().code, -32602);
+        assert_eq!(req.param::<u64>(2).unwrap_err().code, -32602);
+
+        let req: Request = serde_json::from_str(r#"{"method":"getStatus"}"#).unwrap();
+        assert_eq!(req.id, Value::Null);
+        assert!(req.params.is_empty());
+    }
+
+    #[test]
+    fn response_json() {
+        let ok = serde_json::to_string(&Response::new(Value::from(1), Ok(Value::Null))).unwrap();
+        assert_eq!(ok, r#"{"jsonrpc":"2.0","id":1,"result":null}"#);
+        let err = serde_json::to_string(&Response::new(Value::from(2), Err(RpcError::invalid_request()))).unwrap();
+        assert_eq!(err, r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32600,"message":"Invalid request"}}"#);
+    }
+}
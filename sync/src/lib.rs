@@ -0,0 +1,477 @@
+//! Header-first block synchronization.
+//!
+//! Nodes exchange `STATUS` messages periodically. When a peer is far enough
+//! ahead, its canonical headers are downloaded in ranges, the bodies of the new
+//! headers are requested from all peers which have them in parallel, and the
+//! blocks are imported in order through `Chain::insert`. Live blocks are not
+//! processed until the download is finished.
+
+extern crate util;
+extern crate chain;
+extern crate network;
+#[macro_use]
+extern crate log;
+extern crate bincode;
+extern crate parking_lot;
+#[cfg(test)]
+extern crate kvdb;
+
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use bincode::{serialize, Infinite};
+use util::hash::H256;
+use chain::chain::Chain;
+use chain::block::{Block, Header, Body};
+use chain::error::Error;
+use network::connection::Operation;
+use network::msgclass::MsgClass;
+
+/// Max number of headers in one response.
+pub const MAX_HEADERS: u64 = 128;
+/// Max number of bodies in one request.
+pub const MAX_BODIES: usize = 16;
+/// Start syncing when a peer is more than this many blocks ahead.
+const SYNC_DISTANCE: u64 = 5;
+/// How many blocks below our best block to look for the common ancestor.
+const BACKTRACK: u64 = 16;
+/// Seconds between status broadcasts.
+const STATUS_INTERVAL: u64 = 5;
+/// Seconds to wait for a response.
+const REQUEST_TIMEOUT: u64 = 10;
+/// Milliseconds between two maintain ticks.
+const TICK: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    /// Following the network with live blocks.
+    Idle,
+    /// Downloading headers from the sync peer.
+    Headers,
+    /// Downloading the bodies of the queued headers.
+    Bodies,
+}
+
+#[derive(Debug, Clone)]
+struct PeerInfo {
+    height: u64,
+    hash: H256,
+    /// Bodies requested from the peer and when.
+    asking: Option<(Vec<H256>, Instant)>,
+}
+
+struct SyncInner {
+    state: SyncState,
+    peers: HashMap<u32, PeerInfo>,
+    /// Peer whose chain is downloaded.
+    sync_peer: u32,
+    /// Height of the sync peer when syncing started.
+    target: u64,
+    /// Height of the next header to request.
+    start: u64,
+    header_request: Option<Instant>,
+    /// Downloaded headers waiting for their bodies, in chain order.
+    headers: VecDeque<Header>,
+    bodies: HashMap<H256, Body>,
+    last_status: Option<Instant>,
+}
+
+pub struct Synchronizer {
+    id: u32,
+    chain: Arc<Chain>,
+    tx: Mutex<Sender<(u32, Operation, Vec<u8>)>>,
+    inner: Mutex<SyncInner>,
+}
+
+/// Check that `headers` are consecutive blocks starting at height `start`.
+pub fn is_chained(start: u64, headers: &[Header]) -> bool {
+    for (i, header) in headers.iter().enumerate() {
+        if header.height != start + i as u64 {
+            return false;
+        }
+        if i > 0 && header.parent_hash != headers[i - 1].hash() {
+            return false;
+        }
+    }
+    true
+}
+
+impl Synchronizer {
+    pub fn new(tx: Sender<(u32, Operation, Vec<u8>)>, chain: Arc<Chain>, id: u32) -> Self {
+        Synchronizer {
+            id: id,
+            chain: chain,
+            tx: Mutex::new(tx),
+            inner: Mutex::new(SyncInner {
+                state: SyncState::Idle,
+                peers: HashMap::new(),
+                sync_peer: 0,
+                target: 0,
+                start: 0,
+                header_request: None,
+                headers: VecDeque::new(),
+                bodies: HashMap::new(),
+                last_status: None,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> SyncState {
+        self.inner.lock().state
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.state() != SyncState::Idle
+    }
+
+    fn send(&self, to: u32, op: Operation, msg: MsgClass) {
+        let message = serialize(&msg, Infinite).unwrap();
+        self.tx.lock().send((to, op, message)).unwrap();
+    }
+
+    pub fn on_status(&self, origin: u32, height: u64, hash: H256) {
+        let mut inner = self.inner.lock();
+        {
+            let peer = inner.peers.entry(origin).or_insert_with(|| PeerInfo {
+                height: 0,
+                hash: H256::default(),
+                asking: None,
+            });
+            peer.height = height;
+            peer.hash = hash;
+        }
+
+        if inner.state == SyncState::Idle {
+            let current = self.chain.current_height();
+            if height > current + SYNC_DISTANCE {
+                info!("sync with {} from {} to {}", origin, current, height);
+                inner.state = SyncState::Headers;
+                inner.sync_peer = origin;
+                inner.target = height;
+                inner.start = cmp::max(current.saturating_sub(BACKTRACK), 1);
+                self.request_headers(&mut inner);
+            }
+        }
+    }
+
+    pub fn on_get_headers(&self, origin: u32, from: u64, count: u64) {
+        let current = self.chain.current_height();
+        let count = cmp::min(count, MAX_HEADERS);
+        let mut headers = Vec::new();
+        for height in from..from + count {
+            if height > current {
+                break;
+            }
+            match self.chain.block_hash_by_number(height).and_then(|h| self.chain.get_block_header_by_hash(&h)) {
+                Some(rh) => headers.push(rh.header),
+                None => break,
+            }
+        }
+        self.send(origin, Operation::SINGLE, MsgClass::HEADERS(headers));
+    }
+
+    pub fn on_headers(&self, origin: u32, headers: Vec<Header>) {
+        let mut inner = self.inner.lock();
+        if inner.state != SyncState::Headers || origin != inner.sync_peer {
+            return;
+        }
+        inner.header_request = None;
+
+        if headers.is_empty() || !is_chained(inner.start, &headers) {
+            warn!("bad headers from {}", origin);
+            self.reset(&mut inner);
+            return;
+        }
+
+        let first_new = headers.iter().position(|h| self.chain.get_block_header_by_hash(&h.hash()).is_none());
+        match first_new {
+            None => {
+                inner.start += headers.len() as u64;
+                self.next_headers(&mut inner);
+            }
+            Some(i) => {
+                if self.chain.get_block_header_by_hash(&headers[i].parent_hash).is_none() {
+                    // our chain forked before `start`, look further back
+                    if inner.start == 1 {
+                        warn!("no common ancestor with {}", origin);
+                        self.reset(&mut inner);
+                        return;
+                    }
+                    inner.start = cmp::max(inner.start.saturating_sub(MAX_HEADERS), 1);
+                    self.request_headers(&mut inner);
+                    return;
+                }
+                inner.start = headers[headers.len() - 1].height + 1;
+                inner.headers.extend(headers.into_iter().skip(i));
+                inner.state = SyncState::Bodies;
+                self.request_bodies(&mut inner);
+            }
+        }
+    }
+
+    pub fn on_get_bodies(&self, origin: u32, hashes: Vec<H256>) {
+        let bodies = hashes.into_iter()
+            .take(MAX_BODIES)
+            .filter_map(|h| self.chain.get_block_body_by_hash(&h).map(|b| (h, b)))
+            .collect();
+        self.send(origin, Operation::SINGLE, MsgClass::BODIES(bodies));
+    }
+
+    pub fn on_bodies(&self, origin: u32, bodies: Vec<(H256, Body)>) {
+        let mut inner = self.inner.lock();
+        let asked = match inner.peers.get_mut(&origin).and_then(|p| p.asking.take()) {
+            Some((hashes, _)) => hashes,
+            None => return,
+        };
+        if inner.state != SyncState::Bodies {
+            return;
+        }
+
+        for (hash, body) in bodies {
+            if !asked.contains(&hash) {
+                continue;
+            }
+            let valid = inner.headers
+                .iter()
+                .find(|h| h.hash() == hash)
+                .map_or(false, |h| h.transactions_root == body.transactions_root());
+            if valid {
+                inner.bodies.insert(hash, body);
+            } else {
+                warn!("bad body {:?} from {}", hash, origin);
+            }
+        }
+
+        self.import_blocks(&mut inner);
+    }
+
+    fn request_headers(&self, inner: &mut SyncInner) {
+        self.send(inner.sync_peer, Operation::SINGLE, MsgClass::GETHEADERS(inner.start, MAX_HEADERS));
+        inner.header_request = Some(Instant::now());
+    }
+
+    fn next_headers(&self, inner: &mut SyncInner) {
+        if inner.start <= inner.target {
+            inner.state = SyncState::Headers;
+            self.request_headers(inner);
+        } else {
+            info!("sync finished at {}", self.chain.current_height());
+            self.reset(inner);
+        }
+    }
+
+    fn request_bodies(&self, inner: &mut SyncInner) {
+        let asked: HashSet<H256> = inner.peers
+            .values()
+            .filter_map(|p| p.asking.as_ref())
+            .flat_map(|&(ref hashes, _)| hashes.iter().cloned())
+            .collect();
+        let missing: Vec<(u64, H256)> = inner.headers
+            .iter()
+            .map(|h| (h.height, h.hash()))
+            .filter(|&(_, h)| !inner.bodies.contains_key(&h) && !asked.contains(&h))
+            .collect();
+        let free: Vec<u32> = inner.peers
+            .iter()
+            .filter(|&(_, p)| p.asking.is_none())
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut next = 0;
+        for id in free {
+            if next >= missing.len() {
+                break;
+            }
+            let end = cmp::min(next + MAX_BODIES, missing.len());
+            if inner.peers[&id].height < missing[end - 1].0 {
+                continue;
+            }
+            let hashes: Vec<H256> = missing[next..end].iter().map(|&(_, h)| h).collect();
+            next = end;
+            self.send(id, Operation::SINGLE, MsgClass::GETBODIES(hashes.clone()));
+            inner.peers.get_mut(&id).unwrap().asking = Some((hashes, Instant::now()));
+        }
+
+        if inner.peers.values().all(|p| p.asking.is_none()) {
+            warn!("no peer to download bodies from");
+            self.reset(inner);
+        }
+    }
+
+    fn import_blocks(&self, inner: &mut SyncInner) {
+        loop {
+            let hash = match inner.headers.front() {
+                Some(h) => h.hash(),
+                None => break,
+            };
+            let body = match inner.bodies.remove(&hash) {
+                Some(b) => b,
+                None => break,
+            };
+            let header = inner.headers.pop_front().unwrap();
+            match self.chain.insert(Block { header: header, body: body }) {
+                Ok(_) | Err(Error::DuplicateBlock) | Err(Error::FutureBlock) | Err(Error::UnknownParent) => {}
+                Err(err) => {
+                    warn!("sync import block {:?} error {:?}", hash, err);
+                    self.reset(inner);
+                    return;
+                }
+            }
+        }
+
+        if inner.headers.is_empty() {
+            self.next_headers(inner);
+        } else {
+            self.request_bodies(inner);
+        }
+    }
+
+    fn reset(&self, inner: &mut SyncInner) {
+        inner.state = SyncState::Idle;
+        inner.header_request = None;
+        inner.headers.clear();
+        inner.bodies.clear();
+        for peer in inner.peers.values_mut() {
+            peer.asking = None;
+        }
+    }
+
+    /// Broadcast our status and retry timed out requests.
+    pub fn maintain(&self) {
+        let mut inner = self.inner.lock();
+        let now = Instant::now();
+        let timeout = Duration::from_secs(REQUEST_TIMEOUT);
+
+        if inner.last_status.map_or(true, |t| now.duration_since(t) >= Duration::from_secs(STATUS_INTERVAL)) {
+            let (height, hash) = self.chain.get_status();
+            self.send(self.id, Operation::BROADCAST, MsgClass::STATUS(height, hash));
+            inner.last_status = Some(now);
+        }
+
+        match inner.state {
+            SyncState::Headers => {
+                if inner.header_request.map_or(false, |t| now.duration_since(t) >= timeout) {
+                    warn!("headers request to {} timeout", inner.sync_peer);
+                    self.reset(&mut inner);
+                }
+            }
+            SyncState::Bodies => {
+                let mut expired = false;
+                for peer in inner.peers.values_mut() {
+                    if peer.asking.as_ref().map_or(false, |&(_, t)| now.duration_since(t) >= timeout) {
+                        peer.asking = None;
+                        expired = true;
+                    }
+                }
+                if expired {
+                    self.request_bodies(&mut inner);
+                }
+            }
+            SyncState::Idle => {}
+        }
+    }
+}
+
+pub fn start_sync(tx: Sender<(u32, Operation, Vec<u8>)>, chain: Arc<Chain>, id: u32) -> Arc<Synchronizer> {
+    let sync = Arc::new(Synchronizer::new(tx, chain, id));
+    let subtask = sync.clone();
+    thread::spawn(move || {
+        info!("start sync!");
+        loop {
+            subtask.maintain();
+            thread::sleep(Duration::from_millis(TICK));
+        }
+    });
+    sync
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver};
+    use bincode::deserialize;
+    use kvdb::in_memory;
+    use chain::db;
+    use chain::chain::test_helpers::{NOW, new_config, mine};
+    use util::timesource::MockTime;
+
+    fn header(height: u64, parent_hash: H256) -> Header {
+        let mut header = Header::new();
+        header.height = height;
+        header.parent_hash = parent_hash;
+        header
+    }
+
+    #[test]
+    fn chained_headers() {
+        let h1 = header(1, H256::default());
+        let h2 = header(2, h1.hash());
+        let h3 = header(3, h2.hash());
+        assert!(is_chained(1, &[h1.clone(), h2.clone(), h3.clone()]));
+        assert!(is_chained(2, &[h2.clone(), h3.clone()]));
+        assert!(!is_chained(2, &[h1.clone(), h2.clone()]));
+        assert!(!is_chained(1, &[h1.clone(), h3.clone()]));
+        assert!(!is_chained(2, &[h2.clone(), header(3, H256::from(1))]));
+    }
+
+    fn recv(rx: &Receiver<(u32, Operation, Vec<u8>)>) -> (u32, MsgClass) {
+        let (to, _, message) = rx.try_recv().unwrap();
+        (to, deserialize(&message).unwrap())
+    }
+
+    #[test]
+    fn sync_from_peer() {
+        let config = new_config("", Arc::new(MockTime::new(NOW)));
+        let remote = Chain::init(config.clone(), Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap();
+        let local = Chain::init(config.clone(), Arc::new(in_memory(db::NUM_COLUMNS.unwrap()))).unwrap();
+        for _ in 0..SYNC_DISTANCE + 2 {
+            mine(&remote);
+        }
+        let (height, hash) = remote.get_status();
+
+        let (local_tx, local_rx) = channel();
+        let (remote_tx, remote_rx) = channel();
+        let sync = Synchronizer::new(local_tx, local.clone(), 0);
+        let peer = Synchronizer::new(remote_tx, remote.clone(), 1);
+
+        sync.on_status(1, height, hash);
+        assert_eq!(sync.state(), SyncState::Headers);
+        let (from, count) = match recv(&local_rx) {
+            (1, MsgClass::GETHEADERS(from, count)) => (from, count),
+            m => panic!("unexpected {:?}", m),
+        };
+        assert_eq!(from, 1);
+
+        // one range of headers, all of them new
+        peer.on_get_headers(0, from, count);
+        let headers = match recv(&remote_rx) {
+            (0, MsgClass::HEADERS(headers)) => headers,
+            m => panic!("unexpected {:?}", m),
+        };
+        assert_eq!(headers.len() as u64, height);
+        assert_eq!(headers[headers.len() - 1].hash(), hash);
+        sync.on_headers(1, headers);
+        assert_eq!(sync.state(), SyncState::Bodies);
+
+        // one batch of bodies, which completes the chain
+        let hashes = match recv(&local_rx) {
+            (1, MsgClass::GETBODIES(hashes)) => hashes,
+            m => panic!("unexpected {:?}", m),
+        };
+        assert_eq!(hashes.len() as u64, height);
+        peer.on_get_bodies(0, hashes);
+        let bodies = match recv(&remote_rx) {
+            (0, MsgClass::BODIES(bodies)) => bodies,
+            m => panic!("unexpected {:?}", m),
+        };
+        sync.on_bodies(1, bodies);
+
+        assert_eq!(sync.state(), SyncState::Idle);
+        assert_eq!(local.get_status(), (height, hash));
+        assert!(local_rx.try_recv().is_err());
+    }
+}
@@ -18,6 +18,11 @@ impl Filter {
         }
         is_ok
     }
+
+    /// Forget `hash`, so it passes the next check.
+    pub fn remove(&mut self, hash: &H256) {
+        self.inner.remove(hash);
+    }
 }
 
 #[cfg(test)]
@@ -27,13 +32,13 @@ mod tests {
     #[test]
     fn basic() {
         let mut f = Filter::new(2);
-        let mut tx1 = SignedTransaction::new();
+        let mut tx1 = SignedTransaction::new(0);
         tx1.set_data(vec![1]);
-        let mut tx2 = SignedTransaction::new();
+        let mut tx2 = SignedTransaction::new(0);
         tx2.set_data(vec![1]);
-        let mut tx3 = SignedTransaction::new();
+        let mut tx3 = SignedTransaction::new(0);
         tx3.set_data(vec![2]);
-        let mut tx4 = SignedTransaction::new();
+        let mut tx4 = SignedTransaction::new(0);
         tx4.set_data(vec![3]);
 
         assert_eq!(f.check(tx1.cal_hash()), true);
@@ -0,0 +1,98 @@
+//! On-disk journal of the pending transactions, so they survive a restart.
+
+use std::fmt;
+use std::sync::Arc;
+use kvdb::{DBTransaction, KeyValueDB};
+use rlp::{self, UntrustedRlp};
+use chain::transaction::SignedTransaction;
+use util::hash::H256;
+
+/// Transactions keyed by hash in column `col` of `db`.
+pub struct Journal {
+    db: Arc<KeyValueDB>,
+    col: Option<u32>,
+}
+
+impl fmt::Debug for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Journal {{ col: {:?} }}", self.col)
+    }
+}
+
+impl Journal {
+    pub fn new(db: Arc<KeyValueDB>, col: Option<u32>) -> Self {
+        Journal {
+            db: db,
+            col: col,
+        }
+    }
+
+    pub fn insert(&self, tx: &SignedTransaction) {
+        let mut batch = self.db.transaction();
+        batch.put(self.col, &tx.hash(), &rlp::encode(tx));
+        self.write(batch);
+    }
+
+    pub fn remove(&self, hash_list: &[H256]) {
+        let mut batch = self.db.transaction();
+        for hash in hash_list {
+            batch.delete(self.col, hash);
+        }
+        self.write(batch);
+    }
+
+    pub fn clear(&self) {
+        let mut batch = self.db.transaction();
+        for (key, _) in self.db.iter(self.col) {
+            batch.delete(self.col, &key);
+        }
+        self.write(batch);
+    }
+
+    /// All journaled transactions, oldest first. Undecodable entries are skipped.
+    pub fn load(&self) -> Vec<SignedTransaction> {
+        let mut txs: Vec<SignedTransaction> = self.db
+            .iter(self.col)
+            .filter_map(|(_, value)| match UntrustedRlp::new(&value).as_val() {
+                Ok(tx) => Some(tx),
+                Err(e) => {
+                    warn!("bad tx in pool journal {:?}", e);
+                    None
+                }
+            })
+            .collect();
+        txs.sort_by_key(|tx| tx.timestamp());
+        txs
+    }
+
+    fn write(&self, batch: DBTransaction) {
+        if let Err(e) = self.db.write(batch) {
+            warn!("write pool journal error {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvdb::in_memory;
+    use chain::transaction::Transaction;
+
+    #[test]
+    fn insert_remove_load() {
+        let journal = Journal::new(Arc::new(in_memory(0)), None);
+        let tx1 = Transaction::new(2).sign(&H256::from(1));
+        let tx2 = Transaction::new(1).sign(&H256::from(1));
+        let tx3 = Transaction::new(3).sign(&H256::from(1));
+        journal.insert(&tx1);
+        journal.insert(&tx2);
+        journal.insert(&tx3);
+        assert_eq!(journal.load(), vec![tx2.clone(), tx1.clone(), tx3.clone()]);
+
+        journal.remove(&[tx1.hash()]);
+        assert_eq!(journal.load(), vec![tx2, tx3]);
+
+        journal.clear();
+        assert!(journal.load().is_empty());
+    }
+}
@@ -1,8 +1,16 @@
 extern crate lru_cache;
+#[macro_use]
+extern crate log;
+extern crate heapsize;
 extern crate util;
 extern crate chain;
+extern crate kvdb;
+extern crate rlp;
+#[cfg(test)]
+extern crate crypto;
 
 pub mod filter;
+pub mod journal;
 pub mod pool;
 
 pub use pool::*;
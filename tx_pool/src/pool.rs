@@ -1,27 +1,75 @@
 use filter::Filter;
-use std::collections::HashMap;
+use journal::Journal;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::collections::BTreeSet;
+use std::str::FromStr;
+use chain::chain::Chain;
 use chain::transaction::SignedTransaction;
-use util::hash::H256;
+use util::hash::{H256, H512};
+use util::config::PoolConfig;
 use std::cmp::Ordering;
+use std::mem;
+use std::usize;
+use heapsize::HeapSizeOf;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Strategy {
     FIFO,
+    /// Higher `Transaction::priority` first.
     PRIORITY,
+    /// Transactions of the VIP senders first.
     VIP,
 }
 
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" => Ok(Strategy::FIFO),
+            "priority" => Ok(Strategy::PRIORITY),
+            "vip" => Ok(Strategy::VIP),
+            _ => Err(format!("unknown tx pool strategy {}", s)),
+        }
+    }
+}
+
+/// Why `Pool::enqueue` refused a transaction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// Seen recently, pending or already packaged.
+    AlreadyKnown,
+    /// The sender has `max_per_sender` transactions pending.
+    TooManyFromSender,
+    /// The pending transactions which come first fill the pool.
+    PoolFull,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RejectReason::AlreadyKnown => write!(f, "transaction already known"),
+            RejectReason::TooManyFromSender => write!(f, "too many pending transactions from the sender"),
+            RejectReason::PoolFull => write!(f, "transaction pool is full"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct TxOrder {
     hash : H256,
+    /// Higher rank first.
+    rank: u64,
+    /// Arrival order within the same rank.
     order: u64,
 }
 
 impl TxOrder {
-    fn new(hash: H256, order: u64) -> Self {
+    fn new(hash: H256, rank: u64, order: u64) -> Self {
         TxOrder {
             hash: hash,
+            rank: rank,
             order: order,
         }
     }
@@ -41,30 +89,38 @@ impl PartialOrd for TxOrder {
 
 impl Ord for TxOrder {
     fn cmp(&self, b: &TxOrder) -> Ordering {
-        self.order.cmp(&b.order)
+        b.rank.cmp(&self.rank).then(self.order.cmp(&b.order))
     }
 }
 
+#[derive(Debug)]
+struct Entry {
+    tx: SignedTransaction,
+    order: TxOrder,
+    sender: Option<H512>,
+    size: usize,
+}
+
 #[derive(Debug)]
 pub struct Pool {
     package_limit: usize,
     filter: Filter,
     order_set: BTreeSet<TxOrder>,
-    txs: HashMap<H256, SignedTransaction>,
+    txs: HashMap<H256, Entry>,
     strategy: Strategy,
     order: u64,
+    vip: HashSet<H512>,
+    journal: Option<Journal>,
+    max_count: usize,
+    max_bytes: usize,
+    max_per_sender: usize,
+    bytes: usize,
+    senders: HashMap<H512, usize>,
 }
 
 impl Pool {
     pub fn new(capacity: usize, package_limit: usize) -> Self {
-        Pool {
-            package_limit: package_limit,
-            filter: Filter::new(capacity),
-            order_set: BTreeSet::new(),
-            txs: HashMap::new(),
-            strategy: Strategy::FIFO,
-            order: 0,
-        }
+        Pool::new_with_strategy(capacity, package_limit, Strategy::FIFO)
     }
 
     pub fn new_with_strategy(capacity: usize, package_limit: usize, strategy: Strategy) -> Self {
@@ -75,7 +131,70 @@ impl Pool {
             txs: HashMap::new(),
             strategy: strategy,
             order: 0,
+            vip: HashSet::new(),
+            journal: None,
+            max_count: usize::MAX,
+            max_bytes: usize::MAX,
+            max_per_sender: usize::MAX,
+            bytes: 0,
+            senders: HashMap::new(),
+        }
+    }
+
+    /// Fails on an unknown `strategy`.
+    pub fn from_config(config: &PoolConfig) -> Result<Self, String> {
+        let strategy = config.strategy.parse()?;
+        let mut pool = Pool::new_with_strategy(config.capacity, config.package_limit, strategy);
+        pool.set_vip(config.vip_public_keys.iter().cloned().collect());
+        pool.set_limits(config.max_count, config.max_bytes, config.max_per_sender);
+        Ok(pool)
+    }
+
+    /// Set the senders whose transactions come first with the `VIP` strategy.
+    pub fn set_vip(&mut self, vip: HashSet<H512>) {
+        self.vip = vip;
+    }
+
+    /// Bound the number and the size of the pending transactions in total,
+    /// and the number of them from one sender.
+    pub fn set_limits(&mut self, max_count: usize, max_bytes: usize, max_per_sender: usize) {
+        self.max_count = max_count;
+        self.max_bytes = max_bytes;
+        self.max_per_sender = max_per_sender;
+        let evicted = self.evict();
+        self.journal_remove(&evicted);
+    }
+
+    /// Reload the transactions journaled before a restart, dropping those
+    /// already included by `chain` or overdue, and keep journaling to `journal`.
+    pub fn restore(&mut self, journal: Journal, chain: &Chain) -> usize {
+        let txs = journal.load();
+        journal.clear();
+        self.journal = Some(journal);
+        self.reinject(txs, chain)
+    }
+
+    /// Put back transactions seen before, e.g. those of the blocks a reorg
+    /// dropped, unless `chain` already includes them or they are overdue.
+    pub fn reinject(&mut self, txs: Vec<SignedTransaction>, chain: &Chain) -> usize {
+        let (height, hash) = chain.get_status();
+        let txs = chain.filter_transactions(height, hash, txs);
+        self.requeue(txs)
+    }
+
+    fn requeue(&mut self, txs: Vec<SignedTransaction>) -> usize {
+        let mut n = 0;
+        for tx in txs {
+            let hash = tx.hash();
+            if self.txs.contains_key(&hash) {
+                continue;
+            }
+            self.filter.remove(&hash);
+            if self.enqueue(tx, hash).is_ok() {
+                n += 1;
+            }
         }
+        n
     }
 
     fn get_order(&mut self) -> u64 {
@@ -85,70 +204,159 @@ impl Pool {
         order
     }
 
-    #[allow(unused_variables)]
-    fn get_order_by_priority(&mut self, tx: &SignedTransaction) -> u64 {
-        return self.get_order();
+    fn get_rank_by_priority(&self, tx: &SignedTransaction) -> u64 {
+        tx.priority
     }
 
-    #[allow(unused_variables)]
-    fn get_order_by_vip(&mut self, tx: &SignedTransaction) -> u64 {
-        return self.get_order();
+    fn get_rank_by_vip(&self, sender: &Option<H512>) -> u64 {
+        match *sender {
+            Some(ref public) if self.vip.contains(public) => 1,
+            _ => 0,
+        }
     }
 
-    pub fn enqueue(&mut self, tx: SignedTransaction, hash: H256) -> bool {
-        let is_ok = self.filter.check(hash);
-        if is_ok {
-            let order = match self.strategy {
-                Strategy::FIFO => self.get_order(),
-                Strategy::PRIORITY => self.get_order_by_priority(&tx),
-                Strategy::VIP => self.get_order_by_vip(&tx),
+    /// Add a pending transaction, evicting those which come last if the pool is over its limits.
+    pub fn enqueue(&mut self, tx: SignedTransaction, hash: H256) -> Result<(), RejectReason> {
+        if !self.filter.check(hash) {
+            return Err(RejectReason::AlreadyKnown);
+        }
+
+        let sender = tx.recover_public().ok();
+        if let Some(ref sender) = sender {
+            if self.senders.get(sender).cloned().unwrap_or(0) >= self.max_per_sender {
+                trace!("too many txs from {:?}, drop {:?}", sender, hash);
+                // accept it again once the sender's txs are packaged
+                self.filter.remove(&hash);
+                return Err(RejectReason::TooManyFromSender);
+            }
+        }
+
+        let rank = match self.strategy {
+            Strategy::FIFO => 0,
+            Strategy::PRIORITY => self.get_rank_by_priority(&tx),
+            Strategy::VIP => self.get_rank_by_vip(&sender),
+        };
+        let order = self.get_order();
+        let tx_order = TxOrder::new(hash, rank, order);
+        let size = mem::size_of::<SignedTransaction>() + tx.heap_size_of_children();
+
+        self.order_set.insert(tx_order.clone());
+        self.bytes += size;
+        if let Some(sender) = sender {
+            *self.senders.entry(sender).or_insert(0) += 1;
+        }
+        self.txs.insert(hash, Entry {
+            tx: tx,
+            order: tx_order,
+            sender: sender,
+            size: size,
+        });
+
+        let evicted = self.evict();
+        self.journal_remove(&evicted);
+        match self.txs.get(&hash) {
+            Some(entry) => {
+                if let Some(ref journal) = self.journal {
+                    journal.insert(&entry.tx);
+                }
+                Ok(())
+            }
+            None => {
+                // accept it again once there is room
+                self.filter.remove(&hash);
+                Err(RejectReason::PoolFull)
+            }
+        }
+    }
+
+    fn remove(&mut self, hash: &H256) -> Option<SignedTransaction> {
+        let entry = match self.txs.remove(hash) {
+            Some(entry) => entry,
+            None => return None,
+        };
+        self.order_set.remove(&entry.order);
+        self.bytes -= entry.size;
+        if let Some(sender) = entry.sender {
+            let empty = match self.senders.get_mut(&sender) {
+                Some(n) => {
+                    *n -= 1;
+                    *n == 0
+                }
+                None => false,
             };
-            let tx_order = TxOrder::new(hash, order);
-            self.order_set.insert(tx_order);
-            self.txs.insert(hash, tx);
+            if empty {
+                self.senders.remove(&sender);
+            }
         }
-        is_ok
+        Some(entry.tx)
     }
 
-    fn update_order_set(&mut self, hash_list: &[H256]) {
-        self.order_set = self.order_set
-            .iter()
-            .cloned()
-            .filter(|order| !hash_list.contains(&order.hash))
-            .collect();
+    /// The oldest of the lowest ranked transactions.
+    fn lowest(&self) -> Option<H256> {
+        let rank = match self.order_set.iter().next_back() {
+            Some(order) => order.rank,
+            None => return None,
+        };
+        self.order_set.range(TxOrder::new(H256::default(), rank, 0)..).next().map(|order| order.hash)
+    }
+
+    /// Drop transactions until the pool is within its limits.
+    fn evict(&mut self) -> Vec<H256> {
+        let mut evicted = Vec::new();
+        while self.txs.len() > self.max_count || self.bytes > self.max_bytes {
+            let hash = match self.lowest() {
+                Some(hash) => hash,
+                None => break,
+            };
+            trace!("pool is full, evict tx {:?}", hash);
+            self.remove(&hash);
+            evicted.push(hash);
+        }
+        evicted
+    }
+
+    fn journal_remove(&self, hash_list: &[H256]) {
+        if hash_list.is_empty() {
+            return;
+        }
+        if let Some(ref journal) = self.journal {
+            journal.remove(hash_list);
+        }
     }
 
     pub fn update(&mut self, hash_list: &[H256]) {
         for hash in hash_list {
-            self.txs.remove(&hash);
+            self.remove(hash);
         }
-        self.update_order_set(hash_list);
+        self.journal_remove(hash_list);
+    }
+
+    /// Drop the transactions at or before `timestamp`, the left bound of the
+    /// chain, which no block can include any more.
+    pub fn expire(&mut self, timestamp: u64) -> usize {
+        let expired: Vec<H256> = self.txs
+            .iter()
+            .filter(|&(_, entry)| entry.tx.timestamp() <= timestamp)
+            .map(|(hash, _)| *hash)
+            .collect();
+        self.update(&expired);
+        expired.len()
     }
 
     pub fn package(&mut self) -> (Vec<SignedTransaction>, Vec<H256>) {
         let mut tx_list = Vec::new();
         let mut hash_list = Vec::new();
-        let mut n = self.package_limit;
-
-        {
-            let mut iter = self.order_set.iter();
-            loop {
-                let order = iter.next();
-                if order.is_none() {
-                    break;
-                }
-                let hash = order.unwrap().hash;
-                let tx = self.txs.get(&hash);
-                if let Some(tx) = tx {
-                    tx_list.push(tx.clone());
-                    hash_list.push(hash.clone());
-                    n = n - 1;
-                    if n == 0 {
-                        break;
-                    }
-                } else {
-                    panic!("invalid tx order {:?}", order);
+
+        for order in self.order_set.iter() {
+            if tx_list.len() >= self.package_limit {
+                break;
+            }
+            match self.txs.get(&order.hash) {
+                Some(entry) => {
+                    tx_list.push(entry.tx.clone());
+                    hash_list.push(order.hash);
                 }
+                None => warn!("invalid tx order {:?}", order),
             }
         }
 
@@ -158,29 +366,37 @@ impl Pool {
     pub fn len(&self) -> usize {
         self.txs.len()
     }
+
+    /// Memory used by the pending transactions.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
 }
 
-//FIXME
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chain::transaction::SignedTransaction;
+    use chain::transaction::{Transaction, SignedTransaction};
+    use chain::event::ChainEvent;
+    use chain::chain::test_helpers::{SIGNER, new_chain};
+    use crypto::KeyPair;
+
     #[test]
     fn basic() {
         let mut p = Pool::new(2, 1);
-        let mut tx1 = SignedTransaction::new();
+        let mut tx1 = SignedTransaction::new(0);
         tx1.set_data(vec![1]);
-        let mut tx2 = SignedTransaction::new();
+        let mut tx2 = SignedTransaction::new(0);
         tx2.set_data(vec![1]);
-        let mut tx3 = SignedTransaction::new();
+        let mut tx3 = SignedTransaction::new(0);
         tx3.set_data(vec![2]);
-        let mut tx4 = SignedTransaction::new();
+        let mut tx4 = SignedTransaction::new(0);
         tx4.set_data(vec![3]);
 
-        assert_eq!(p.enqueue(tx1.clone(), tx1.cal_hash()), true);
-        assert_eq!(p.enqueue(tx2.clone(), tx2.cal_hash()), false);
-        assert_eq!(p.enqueue(tx3.clone(), tx3.cal_hash()), true);
-        assert_eq!(p.enqueue(tx4.clone(), tx4.cal_hash()), true);
+        assert_eq!(p.enqueue(tx1.clone(), tx1.cal_hash()), Ok(()));
+        assert_eq!(p.enqueue(tx2.clone(), tx2.cal_hash()), Err(RejectReason::AlreadyKnown));
+        assert_eq!(p.enqueue(tx3.clone(), tx3.cal_hash()), Ok(()));
+        assert_eq!(p.enqueue(tx4.clone(), tx4.cal_hash()), Ok(()));
 
         assert_eq!(p.len(), 3);
         p.update(&vec![tx1.cal_hash()]);
@@ -190,4 +406,169 @@ mod tests {
         assert_eq!(p.package().0, vec![tx4]);
         assert_eq!(p.len(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn priority() {
+        let mut p = Pool::new_with_strategy(10, 10, Strategy::PRIORITY);
+        let txs: Vec<SignedTransaction> = [1, 3, 2, 3].iter().enumerate().map(|(i, &priority)| {
+            let mut tx = Transaction::new(i as u64);
+            tx.set_priority(priority);
+            tx.sign(&H256::from(1))
+        }).collect();
+        for tx in &txs {
+            assert_eq!(p.enqueue(tx.clone(), tx.hash()), Ok(()));
+        }
+        let (package, _) = p.package();
+        assert_eq!(package, vec![txs[1].clone(), txs[3].clone(), txs[2].clone(), txs[0].clone()]);
+    }
+
+    #[test]
+    fn vip() {
+        let vip_key = H256::from(1);
+        let mut p = Pool::new_with_strategy(10, 10, Strategy::VIP);
+        p.set_vip(vec![*KeyPair::from_privkey(vip_key).unwrap().pubkey()].into_iter().collect());
+        let tx1 = Transaction::new(1).sign(&H256::from(2));
+        let tx2 = Transaction::new(2).sign(&vip_key);
+        let tx3 = Transaction::new(3).sign(&H256::from(2));
+        let tx4 = Transaction::new(4).sign(&vip_key);
+        for tx in &[&tx1, &tx2, &tx3, &tx4] {
+            assert_eq!(p.enqueue((*tx).clone(), tx.hash()), Ok(()));
+        }
+        assert_eq!(p.package().0, vec![tx2, tx4, tx1, tx3]);
+    }
+
+    #[test]
+    fn evict_by_count() {
+        let mut p = Pool::new_with_strategy(10, 10, Strategy::PRIORITY);
+        p.set_limits(2, usize::MAX, usize::MAX);
+        let txs: Vec<SignedTransaction> = [1, 1, 2, 0].iter().enumerate().map(|(i, &priority)| {
+            let mut tx = Transaction::new(i as u64);
+            tx.set_priority(priority);
+            tx.sign(&H256::from(i as u64 + 1))
+        }).collect();
+        assert_eq!(p.enqueue(txs[0].clone(), txs[0].hash()), Ok(()));
+        assert_eq!(p.enqueue(txs[1].clone(), txs[1].hash()), Ok(()));
+        // the oldest of the lowest priority goes
+        assert_eq!(p.enqueue(txs[2].clone(), txs[2].hash()), Ok(()));
+        assert_eq!(p.package().0, vec![txs[2].clone(), txs[1].clone()]);
+        // lower than anything pending
+        assert_eq!(p.enqueue(txs[3].clone(), txs[3].hash()), Err(RejectReason::PoolFull));
+        assert_eq!(p.len(), 2);
+
+        p.set_limits(3, usize::MAX, usize::MAX);
+        assert_eq!(p.enqueue(txs[3].clone(), txs[3].hash()), Ok(()));
+        assert_eq!(p.len(), 3);
+    }
+
+    #[test]
+    fn evict_by_bytes() {
+        let mut p = Pool::new(10, 10);
+        let mut tx1 = SignedTransaction::new(1);
+        tx1.set_data(vec![0; 100]);
+        let mut tx2 = SignedTransaction::new(2);
+        tx2.set_data(vec![0; 100]);
+        assert_eq!(p.enqueue(tx1.clone(), tx1.cal_hash()), Ok(()));
+        let size = p.bytes();
+        p.set_limits(usize::MAX, size * 2 - 1, usize::MAX);
+        assert_eq!(p.enqueue(tx2.clone(), tx2.cal_hash()), Ok(()));
+        assert_eq!(p.package().0, vec![tx2]);
+        // the allocator may round the data of the two differently
+        assert!(p.bytes() < size * 2);
+    }
+
+    #[test]
+    fn per_sender_limit() {
+        let mut p = Pool::new(10, 10);
+        p.set_limits(usize::MAX, usize::MAX, 2);
+        let tx1 = Transaction::new(1).sign(&H256::from(1));
+        let tx2 = Transaction::new(2).sign(&H256::from(1));
+        let tx3 = Transaction::new(3).sign(&H256::from(1));
+        let tx4 = Transaction::new(4).sign(&H256::from(2));
+        assert_eq!(p.enqueue(tx1.clone(), tx1.hash()), Ok(()));
+        assert_eq!(p.enqueue(tx2.clone(), tx2.hash()), Ok(()));
+        assert_eq!(p.enqueue(tx3.clone(), tx3.hash()), Err(RejectReason::TooManyFromSender));
+        assert_eq!(p.enqueue(tx4.clone(), tx4.hash()), Ok(()));
+
+        p.update(&[tx1.hash()]);
+        assert_eq!(p.enqueue(tx3.clone(), tx3.hash()), Ok(()));
+        assert_eq!(p.len(), 3);
+    }
+
+    #[test]
+    fn expire() {
+        let mut p = Pool::new(10, 10);
+        let txs: Vec<SignedTransaction> = (1..5).map(|t| SignedTransaction::new(t)).collect();
+        for tx in &txs {
+            assert_eq!(p.enqueue(tx.clone(), tx.hash()), Ok(()));
+        }
+        assert_eq!(p.expire(2), 2);
+        assert_eq!(p.package().0, txs[2..].to_vec());
+    }
+
+    #[test]
+    fn requeue() {
+        let mut p = Pool::new(10, 10);
+        let tx1 = SignedTransaction::new(1);
+        let tx2 = SignedTransaction::new(2);
+        assert_eq!(p.enqueue(tx1.clone(), tx1.hash()), Ok(()));
+        assert_eq!(p.enqueue(tx2.clone(), tx2.hash()), Ok(()));
+        let (package, hash_list) = p.package();
+        p.update(&hash_list);
+        assert_eq!(p.len(), 0);
+        assert_eq!(p.enqueue(tx1.clone(), tx1.hash()), Err(RejectReason::AlreadyKnown));
+
+        assert_eq!(p.requeue(package), 2);
+        assert_eq!(p.requeue(vec![tx1.clone()]), 0);
+        assert_eq!(p.package().0, vec![tx1, tx2]);
+    }
+
+    #[test]
+    fn strategy_from_str() {
+        assert_eq!("fifo".parse::<Strategy>(), Ok(Strategy::FIFO));
+        assert_eq!("PRIORITY".parse::<Strategy>(), Ok(Strategy::PRIORITY));
+        assert_eq!("vip".parse::<Strategy>(), Ok(Strategy::VIP));
+        assert!("lifo".parse::<Strategy>().is_err());
+
+        let mut config = PoolConfig::default();
+        assert!(Pool::from_config(&config).is_ok());
+        config.strategy = "lifo".to_owned();
+        assert_eq!(Pool::from_config(&config).err(), Some("unknown tx pool strategy lifo".to_owned()));
+    }
+
+    #[test]
+    fn reinject_retracted() {
+        let key = H256::from(SIGNER);
+        let chain = new_chain("");
+        let (_, genesis) = chain.get_status();
+        let events = chain.subscribe();
+        let txs: Vec<SignedTransaction> = (1000..1003).map(|t| Transaction::new(t).sign(&key)).collect();
+        let mut p = Pool::new(10, 10);
+        for tx in &txs {
+            assert_eq!(p.enqueue(tx.clone(), tx.hash()), Ok(()));
+        }
+        let (package, hash_list) = p.package();
+        let a1 = chain.gen_block(0, genesis, 10, vec![1], package).unwrap();
+        p.update(&hash_list);
+        assert_eq!(p.len(), 0);
+
+        // a fork including only the first transaction wins
+        let b1 = chain.gen_block(0, genesis, 11, vec![2], vec![txs[0].clone()]).unwrap();
+        let b2 = chain.gen_block(1, b1.hash(), 20, vec![3], Vec::new()).unwrap();
+        assert_eq!(chain.get_status(), (2, b2.hash()));
+        let retracted = events.try_iter().filter_map(|e| match e {
+            ChainEvent::Reorg { retracted, .. } => Some(retracted),
+            _ => None,
+        }).next().unwrap();
+        assert_eq!(retracted, vec![a1.hash()]);
+
+        let body = chain.get_block_body_by_hash(&a1.hash()).unwrap();
+        assert_eq!(p.reinject(body.transactions.clone(), &chain), 2);
+        assert_eq!(p.package().0, txs[1..].to_vec());
+        // nor twice
+        assert_eq!(p.reinject(body.transactions, &chain), 0);
+
+        // those out of the validity window are gone for good
+        let overdue = Transaction::new(chain.expire_timestamp()).sign(&key);
+        assert_eq!(p.reinject(vec![overdue], &chain), 0);
+    }
+}
@@ -1,13 +1,18 @@
 // build.rs
 
 // Bring in a dependency on an externally maintained `gcc` package which manages
-// invoking the C compiler.
+// invoking the C compiler. Only the pbc backend has C code.
+#[cfg(feature = "pbc")]
 extern crate gcc;
 
+#[cfg(feature = "pbc")]
 fn main() {
     gcc::Build::new()
                 .file("src/bls.c")
                 .include("/usr/local/include/pbc")
                 .static_flag(true)
                 .compile("libbls.a");
-}
\ No newline at end of file
+}
+
+#[cfg(not(feature = "pbc"))]
+fn main() {}
@@ -0,0 +1,16 @@
+//! Print BLS key pairs in the format of admintool/bls.keypairs,
+//! the private key, the public key and the generator on one line each.
+
+extern crate bls;
+
+use std::env;
+
+fn main() {
+    let count = env::args().nth(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+    for _ in 0..count {
+        let (private_key, public_key, g) = bls::key_gen().expect("generate BLS keys");
+        println!("{:?}", private_key);
+        println!("{:?}", public_key);
+        println!("{:?}", g);
+    }
+}
@@ -0,0 +1,268 @@
+//! The groups G1 on y^2 = x^3 + 3 over Fq and G2 on the twist
+//! y^2 = x^3 + 3 / (9 + u) over Fq2, with their compressed encodings.
+
+use tiny_keccak::keccak256;
+
+use super::field::{Field, Fq, Fq2, limbs_from_bytes, limbs_to_bytes, limbs_lt};
+
+/// The group order r, little-endian limbs.
+pub const ORDER: [u64; 4] = [0x43e1f593f0000001, 0x2833e84879b97091, 0xb85045b68181585d, 0x30644e72e131a029];
+
+/// 3 / (9 + u)
+const TWIST_B: ([u64; 4], [u64; 4]) = (
+    [0x3267e6dc24a138e5, 0xb5b4c5e559dbefa3, 0x81be18991be06ac3, 0x2b149d40ceb8aaae],
+    [0xe4a2bd0685c315d2, 0xa74fa084e52d1852, 0xcd2cafadeed8fdf4, 0x009713b03af0fed4],
+);
+
+/// The generator of G2 used by the Ethereum precompiles.
+const G2_X: ([u64; 4], [u64; 4]) = (
+    [0x46debd5cd992f6ed, 0x674322d4f75edadd, 0x426a00665e5c4479, 0x1800deef121f1e76],
+    [0x97e485b7aef312c2, 0xf1aa493335a9e712, 0x7260bfb731fb5d25, 0x198e9393920d483a],
+);
+const G2_Y: ([u64; 4], [u64; 4]) = (
+    [0x4ce6cc0166fa7daa, 0xe3d1e7690c43d37b, 0x4aab71808dcb408f, 0x12c85ea5db8c6deb],
+    [0x55acdadcd122975b, 0xbc4b313370b38ef3, 0xec9e99ad690c3395, 0x090689d0585ff075],
+);
+
+/// Set in the first byte of a compressed point when y is odd.
+const ODD_FLAG: u8 = 0x80;
+
+/// A point (X / Z^2, Y / Z^3) in Jacobian coordinates, Z = 0 at infinity.
+#[derive(Clone, Copy, Debug)]
+pub struct Point<F> {
+    pub x: F,
+    pub y: F,
+    pub z: F,
+}
+
+pub type G1 = Point<Fq>;
+pub type G2 = Point<Fq2>;
+
+impl<F: Field> Point<F> {
+    pub fn infinity() -> Point<F> {
+        Point { x: F::one(), y: F::one(), z: F::zero() }
+    }
+
+    pub fn from_affine(x: F, y: F) -> Point<F> {
+        Point { x, y, z: F::one() }
+    }
+
+    pub fn is_infinity(&self) -> bool {
+        self.z.is_zero()
+    }
+
+    pub fn to_affine(self) -> Option<(F, F)> {
+        let zi = self.z.inverse()?;
+        let zi2 = zi.square();
+        Some((self.x * zi2, self.y * zi2 * zi))
+    }
+
+    #[cfg(test)]
+    pub fn is_on_curve(&self, b: F) -> bool {
+        if self.is_infinity() {
+            return true;
+        }
+        let z2 = self.z.square();
+        let z6 = z2.square() * z2;
+        self.y.square() == self.x.square() * self.x + b * z6
+    }
+
+    pub fn neg(&self) -> Point<F> {
+        Point { x: self.x, y: -self.y, z: self.z }
+    }
+
+    pub fn double(&self) -> Point<F> {
+        if self.is_infinity() {
+            return *self;
+        }
+        let a = self.x.square();
+        let b = self.y.square();
+        let c = b.square();
+        let d = ((self.x + b).square() - a - c).double();
+        let e = a.double() + a;
+        let f = e.square();
+        let x = f - d.double();
+        let y = e * (d - x) - c.double().double().double();
+        let z = (self.y * self.z).double();
+        Point { x, y, z }
+    }
+
+    pub fn add(&self, other: &Point<F>) -> Point<F> {
+        if self.is_infinity() {
+            return *other;
+        }
+        if other.is_infinity() {
+            return *self;
+        }
+        let z1z1 = self.z.square();
+        let z2z2 = other.z.square();
+        let u1 = self.x * z2z2;
+        let u2 = other.x * z1z1;
+        let s1 = self.y * other.z * z2z2;
+        let s2 = other.y * self.z * z1z1;
+        let h = u2 - u1;
+        if h.is_zero() {
+            return if s1 == s2 { self.double() } else { Point::infinity() };
+        }
+        let i = h.double().square();
+        let j = h * i;
+        let r = (s2 - s1).double();
+        let v = u1 * i;
+        let x = r.square() - j - v.double();
+        let y = r * (v - x) - (s1 * j).double();
+        let z = ((self.z + other.z).square() - z1z1 - z2z2) * h;
+        Point { x, y, z }
+    }
+
+    /// Multiply by a scalar given as little-endian limbs.
+    pub fn mul(&self, scalar: &[u64]) -> Point<F> {
+        let mut res = Point::infinity();
+        for limb in scalar.iter().rev() {
+            for i in (0..64).rev() {
+                res = res.double();
+                if (limb >> i) & 1 == 1 {
+                    res = res.add(self);
+                }
+            }
+        }
+        res
+    }
+}
+
+impl<F: Field> PartialEq for Point<F> {
+    fn eq(&self, other: &Point<F>) -> bool {
+        if self.is_infinity() || other.is_infinity() {
+            return self.is_infinity() && other.is_infinity();
+        }
+        let z1z1 = self.z.square();
+        let z2z2 = other.z.square();
+        self.x * z2z2 == other.x * z1z1 && self.y * z2z2 * other.z == other.y * z1z1 * self.z
+    }
+}
+
+pub fn g1_b() -> Fq {
+    Fq::from_u64(3)
+}
+
+pub fn g2_b() -> Fq2 {
+    Fq2::new(Fq::from_raw(TWIST_B.0), Fq::from_raw(TWIST_B.1))
+}
+
+pub fn g2_generator() -> G2 {
+    let x = Fq2::new(Fq::from_raw(G2_X.0), Fq::from_raw(G2_X.1));
+    let y = Fq2::new(Fq::from_raw(G2_Y.0), Fq::from_raw(G2_Y.1));
+    G2::from_affine(x, y)
+}
+
+/// A scalar in [1, r) from 32 big-endian bytes.
+pub fn scalar_from_bytes(bytes: &[u8]) -> Option<[u64; 4]> {
+    if bytes.len() != 32 {
+        return None;
+    }
+    let scalar = limbs_from_bytes(bytes);
+    if scalar != [0; 4] && limbs_lt(&scalar, &ORDER) { Some(scalar) } else { None }
+}
+
+pub fn scalar_to_bytes(scalar: &[u64; 4]) -> [u8; 32] {
+    limbs_to_bytes(scalar)
+}
+
+/// Hash to G1 by try-and-increment, G1 has cofactor 1.
+pub fn hash_to_g1(msg: &[u8]) -> G1 {
+    let mut data = msg.to_vec();
+    data.extend_from_slice(&[0; 4]);
+    let mut counter = 0u32;
+    loop {
+        let len = data.len();
+        for i in 0..4 {
+            data[len - 4 + i] = (counter >> (8 * (3 - i))) as u8;
+        }
+        let mut hash = keccak256(&data);
+        // p < 2^254, keep the candidates in range most of the time
+        hash[0] &= 0x3f;
+        if let Some(x) = Fq::from_bytes(&hash) {
+            if let Some(y) = (x.square() * x + g1_b()).sqrt() {
+                return G1::from_affine(x, y);
+            }
+        }
+        counter += 1;
+    }
+}
+
+/// x as 32 bytes with the parity of y in the top bit.
+pub fn g1_to_bytes(point: &G1) -> Vec<u8> {
+    let (x, y) = point.to_affine().expect("G1 point at infinity is never encoded");
+    let mut bytes = x.to_bytes().to_vec();
+    if y.is_odd() {
+        bytes[0] |= ODD_FLAG;
+    }
+    bytes
+}
+
+pub fn g1_from_bytes(bytes: &[u8]) -> Option<G1> {
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut x = bytes.to_vec();
+    let odd = x[0] & ODD_FLAG != 0;
+    x[0] &= !ODD_FLAG;
+    let x = Fq::from_bytes(&x)?;
+    let y = (x.square() * x + g1_b()).sqrt()?;
+    let y = if y.is_odd() == odd { y } else { -y };
+    Some(G1::from_affine(x, y))
+}
+
+/// x as c1 || c0, 64 bytes, with the parity of y in the top bit.
+pub fn g2_to_bytes(point: &G2) -> Vec<u8> {
+    let (x, y) = point.to_affine().expect("G2 point at infinity is never encoded");
+    let mut bytes = x.c1.to_bytes().to_vec();
+    bytes.extend_from_slice(&x.c0.to_bytes());
+    if y.is_odd() {
+        bytes[0] |= ODD_FLAG;
+    }
+    bytes
+}
+
+/// Decode a point of G2, None unless it is on the twist and of order r.
+pub fn g2_from_bytes(bytes: &[u8]) -> Option<G2> {
+    if bytes.len() != 64 {
+        return None;
+    }
+    let mut c1 = bytes[..32].to_vec();
+    let odd = c1[0] & ODD_FLAG != 0;
+    c1[0] &= !ODD_FLAG;
+    let x = Fq2::new(Fq::from_bytes(&bytes[32..])?, Fq::from_bytes(&c1)?);
+    let y = (x.square() * x + g2_b()).sqrt()?;
+    let y = if y.is_odd() == odd { y } else { -y };
+    let point = G2::from_affine(x, y);
+    if point.mul(&ORDER).is_infinity() { Some(point) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generator_has_order_r() {
+        let g = g2_generator();
+        assert!(g.is_on_curve(g2_b()));
+        assert!(g.mul(&ORDER).is_infinity());
+        let p = hash_to_g1(b"sleepy");
+        assert!(p.is_on_curve(g1_b()));
+        assert!(p.mul(&ORDER).is_infinity());
+        assert_eq!(p.add(&p), p.double());
+        assert_eq!(p.add(&p.neg()), G1::infinity());
+    }
+
+    #[test]
+    fn compression() {
+        let p = hash_to_g1(b"sleepy").mul(&[5, 0, 0, 0]);
+        assert_eq!(g1_from_bytes(&g1_to_bytes(&p)), Some(p));
+        assert_eq!(g1_from_bytes(&g1_to_bytes(&p.neg())), Some(p.neg()));
+
+        let q = g2_generator().mul(&[7, 0, 0, 0]);
+        assert_eq!(g2_from_bytes(&g2_to_bytes(&q)), Some(q));
+        assert_eq!(g2_from_bytes(&g2_to_bytes(&q.neg())), Some(q.neg()));
+        assert_eq!(g2_from_bytes(&[0xff; 64]), None);
+    }
+}
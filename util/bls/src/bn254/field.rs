@@ -0,0 +1,454 @@
+//! The base field Fq of BN254 and the tower Fq2 = Fq[u] / (u^2 + 1),
+//! Fq6 = Fq2[v] / (v^3 - (9 + u)) and Fq12 = Fq6[w] / (w^2 - v) over it.
+
+use std::ops::{Add, Sub, Mul, Neg};
+
+/// The field modulus p, little-endian limbs.
+pub const MODULUS: [u64; 4] = [0x3c208c16d87cfd47, 0x97816a916871ca8d, 0xb85045b68181585d, 0x30644e72e131a029];
+/// -p^-1 mod 2^64.
+const INV: u64 = 0x87d20782e4866389;
+/// 2^256 mod p, the Montgomery form of one.
+const R1: [u64; 4] = [0xd35d438dc58f0d9d, 0x0a78eb28f5c70b3d, 0x666ea36f7879462c, 0x0e0a77c19a07df2f];
+/// 2^512 mod p.
+const R2: [u64; 4] = [0xf32cfc5b538afa89, 0xb5e71911d44501fb, 0x47ab1eff0a417ff6, 0x06d89f71cab8351f];
+/// p - 2, to invert by Fermat.
+const MODULUS_MINUS_TWO: [u64; 4] = [0x3c208c16d87cfd45, 0x97816a916871ca8d, 0xb85045b68181585d, 0x30644e72e131a029];
+/// (p + 1) / 4, to take square roots as p = 3 mod 4.
+const SQRT_EXP: [u64; 4] = [0x4f082305b61f3f52, 0x65e05aa45a1c72a3, 0x6e14116da0605617, 0x0c19139cb84c680a];
+
+pub trait Field: Copy + PartialEq + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Neg<Output = Self> {
+    fn zero() -> Self;
+    fn one() -> Self;
+    fn inverse(&self) -> Option<Self>;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::zero()
+    }
+
+    fn square(&self) -> Self {
+        *self * *self
+    }
+
+    fn double(&self) -> Self {
+        *self + *self
+    }
+
+    /// Raise to the power given by little-endian limbs.
+    fn pow(&self, exp: &[u64]) -> Self {
+        let mut res = Self::one();
+        for limb in exp.iter().rev() {
+            for i in (0..64).rev() {
+                res = res.square();
+                if (limb >> i) & 1 == 1 {
+                    res = res * *self;
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Whether `a < b`, both little-endian limbs.
+pub fn limbs_lt(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if a[i] != b[i] {
+            return a[i] < b[i];
+        }
+    }
+    false
+}
+
+/// Read 32 big-endian bytes into little-endian limbs.
+pub fn limbs_from_bytes(bytes: &[u8]) -> [u64; 4] {
+    assert_eq!(bytes.len(), 32);
+    let mut limbs = [0u64; 4];
+    for (i, byte) in bytes.iter().enumerate() {
+        let limb = 3 - i / 8;
+        limbs[limb] = (limbs[limb] << 8) | *byte as u64;
+    }
+    limbs
+}
+
+/// Write little-endian limbs as 32 big-endian bytes.
+pub fn limbs_to_bytes(limbs: &[u64; 4]) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for i in 0..32 {
+        bytes[i] = (limbs[3 - i / 8] >> (8 * (7 - i % 8))) as u8;
+    }
+    bytes
+}
+
+fn add_limbs(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut out = [0u64; 4];
+    let mut carry = 0u128;
+    for i in 0..4 {
+        let s = a[i] as u128 + b[i] as u128 + carry;
+        out[i] = s as u64;
+        carry = s >> 64;
+    }
+    out
+}
+
+fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut out = [0u64; 4];
+    let mut borrow = false;
+    for i in 0..4 {
+        let (d1, b1) = a[i].overflowing_sub(b[i]);
+        let (d2, b2) = d1.overflowing_sub(borrow as u64);
+        out[i] = d2;
+        borrow = b1 || b2;
+    }
+    (out, borrow)
+}
+
+/// Montgomery multiplication, a * b / 2^256 mod p.
+fn mont_mul(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut t = [0u64; 6];
+    for &bi in b.iter() {
+        let mut carry = 0u128;
+        for j in 0..4 {
+            let s = t[j] as u128 + a[j] as u128 * bi as u128 + carry;
+            t[j] = s as u64;
+            carry = s >> 64;
+        }
+        let s = t[4] as u128 + carry;
+        t[4] = s as u64;
+        t[5] = (s >> 64) as u64;
+
+        let m = t[0].wrapping_mul(INV);
+        let s = t[0] as u128 + m as u128 * MODULUS[0] as u128;
+        let mut carry = s >> 64;
+        for j in 1..4 {
+            let s = t[j] as u128 + m as u128 * MODULUS[j] as u128 + carry;
+            t[j - 1] = s as u64;
+            carry = s >> 64;
+        }
+        let s = t[4] as u128 + carry;
+        t[3] = s as u64;
+        t[4] = t[5] + (s >> 64) as u64;
+    }
+    let out = [t[0], t[1], t[2], t[3]];
+    if t[4] != 0 || !limbs_lt(&out, &MODULUS) {
+        sub_limbs(&out, &MODULUS).0
+    } else {
+        out
+    }
+}
+
+/// An element of Fq in Montgomery form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fq([u64; 4]);
+
+impl Fq {
+    /// From canonical little-endian limbs below the modulus.
+    pub fn from_raw(limbs: [u64; 4]) -> Fq {
+        Fq(mont_mul(&limbs, &R2))
+    }
+
+    pub fn from_u64(value: u64) -> Fq {
+        Fq::from_raw([value, 0, 0, 0])
+    }
+
+    /// Canonical little-endian limbs.
+    pub fn to_raw(self) -> [u64; 4] {
+        mont_mul(&self.0, &[1, 0, 0, 0])
+    }
+
+    /// From 32 big-endian bytes, None unless below the modulus.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Fq> {
+        let limbs = limbs_from_bytes(bytes);
+        if limbs_lt(&limbs, &MODULUS) { Some(Fq::from_raw(limbs)) } else { None }
+    }
+
+    pub fn to_bytes(self) -> [u8; 32] {
+        limbs_to_bytes(&self.to_raw())
+    }
+
+    pub fn is_odd(&self) -> bool {
+        self.to_raw()[0] & 1 == 1
+    }
+
+    pub fn sqrt(&self) -> Option<Fq> {
+        let root = self.pow(&SQRT_EXP);
+        if root.square() == *self { Some(root) } else { None }
+    }
+}
+
+impl Field for Fq {
+    fn zero() -> Fq {
+        Fq([0; 4])
+    }
+
+    fn one() -> Fq {
+        Fq(R1)
+    }
+
+    fn inverse(&self) -> Option<Fq> {
+        if self.is_zero() { None } else { Some(self.pow(&MODULUS_MINUS_TWO)) }
+    }
+}
+
+impl Add for Fq {
+    type Output = Fq;
+
+    fn add(self, other: Fq) -> Fq {
+        // both are below p < 2^254, the sum can't overflow
+        let sum = add_limbs(&self.0, &other.0);
+        if limbs_lt(&sum, &MODULUS) { Fq(sum) } else { Fq(sub_limbs(&sum, &MODULUS).0) }
+    }
+}
+
+impl Sub for Fq {
+    type Output = Fq;
+
+    fn sub(self, other: Fq) -> Fq {
+        let (diff, borrow) = sub_limbs(&self.0, &other.0);
+        if borrow { Fq(add_limbs(&diff, &MODULUS)) } else { Fq(diff) }
+    }
+}
+
+impl Mul for Fq {
+    type Output = Fq;
+
+    fn mul(self, other: Fq) -> Fq {
+        Fq(mont_mul(&self.0, &other.0))
+    }
+}
+
+impl Neg for Fq {
+    type Output = Fq;
+
+    fn neg(self) -> Fq {
+        Fq::zero() - self
+    }
+}
+
+macro_rules! impl_componentwise {
+    ($t:ident, $($c:ident),+) => {
+        impl Add for $t {
+            type Output = $t;
+
+            fn add(self, other: $t) -> $t {
+                $t { $($c: self.$c + other.$c),+ }
+            }
+        }
+
+        impl Sub for $t {
+            type Output = $t;
+
+            fn sub(self, other: $t) -> $t {
+                $t { $($c: self.$c - other.$c),+ }
+            }
+        }
+
+        impl Neg for $t {
+            type Output = $t;
+
+            fn neg(self) -> $t {
+                $t { $($c: -self.$c),+ }
+            }
+        }
+    }
+}
+
+/// c0 + c1 u
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fq2 {
+    pub c0: Fq,
+    pub c1: Fq,
+}
+
+impl_componentwise!(Fq2, c0, c1);
+
+impl Fq2 {
+    pub fn new(c0: Fq, c1: Fq) -> Fq2 {
+        Fq2 { c0, c1 }
+    }
+
+    pub fn scale(&self, k: Fq) -> Fq2 {
+        Fq2::new(self.c0 * k, self.c1 * k)
+    }
+
+    /// Multiply by the cubic non-residue 9 + u.
+    pub fn mul_by_nonresidue(&self) -> Fq2 {
+        let nine = |x: Fq| x.double().double().double() + x;
+        Fq2::new(nine(self.c0) - self.c1, nine(self.c1) + self.c0)
+    }
+
+    /// Sign bit for point compression, the parity of the first non-zero coefficient.
+    pub fn is_odd(&self) -> bool {
+        if self.c0.is_zero() { self.c1.is_odd() } else { self.c0.is_odd() }
+    }
+
+    pub fn sqrt(&self) -> Option<Fq2> {
+        if self.c1.is_zero() {
+            return match self.c0.sqrt() {
+                Some(x) => Some(Fq2::new(x, Fq::zero())),
+                None => (-self.c0).sqrt().map(|y| Fq2::new(Fq::zero(), y)),
+            };
+        }
+        // (x0 + x1 u)^2 = c0 + c1 u gives x0^2 = (c0 +- sqrt(c0^2 + c1^2)) / 2 and x1 = c1 / 2 x0
+        let norm = (self.c0.square() + self.c1.square()).sqrt()?;
+        let half = Fq::from_u64(2).inverse()?;
+        let x0 = match ((self.c0 + norm) * half).sqrt() {
+            Some(x0) => x0,
+            None => ((self.c0 - norm) * half).sqrt()?,
+        };
+        let x1 = self.c1 * x0.double().inverse()?;
+        let root = Fq2::new(x0, x1);
+        if root.square() == *self { Some(root) } else { None }
+    }
+}
+
+impl Field for Fq2 {
+    fn zero() -> Fq2 {
+        Fq2::new(Fq::zero(), Fq::zero())
+    }
+
+    fn one() -> Fq2 {
+        Fq2::new(Fq::one(), Fq::zero())
+    }
+
+    fn inverse(&self) -> Option<Fq2> {
+        let t = (self.c0.square() + self.c1.square()).inverse()?;
+        Some(Fq2::new(self.c0 * t, -(self.c1 * t)))
+    }
+}
+
+impl Mul for Fq2 {
+    type Output = Fq2;
+
+    fn mul(self, other: Fq2) -> Fq2 {
+        let v0 = self.c0 * other.c0;
+        let v1 = self.c1 * other.c1;
+        Fq2::new(v0 - v1, (self.c0 + self.c1) * (other.c0 + other.c1) - v0 - v1)
+    }
+}
+
+/// c0 + c1 v + c2 v^2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fq6 {
+    pub c0: Fq2,
+    pub c1: Fq2,
+    pub c2: Fq2,
+}
+
+impl_componentwise!(Fq6, c0, c1, c2);
+
+impl Fq6 {
+    pub fn new(c0: Fq2, c1: Fq2, c2: Fq2) -> Fq6 {
+        Fq6 { c0, c1, c2 }
+    }
+
+    /// Multiply by v.
+    pub fn mul_by_v(&self) -> Fq6 {
+        Fq6::new(self.c2.mul_by_nonresidue(), self.c0, self.c1)
+    }
+}
+
+impl Field for Fq6 {
+    fn zero() -> Fq6 {
+        Fq6::new(Fq2::zero(), Fq2::zero(), Fq2::zero())
+    }
+
+    fn one() -> Fq6 {
+        Fq6::new(Fq2::one(), Fq2::zero(), Fq2::zero())
+    }
+
+    fn inverse(&self) -> Option<Fq6> {
+        let t0 = self.c0.square() - (self.c1 * self.c2).mul_by_nonresidue();
+        let t1 = self.c2.square().mul_by_nonresidue() - self.c0 * self.c1;
+        let t2 = self.c1.square() - self.c0 * self.c2;
+        let d = self.c0 * t0 + (self.c2 * t1 + self.c1 * t2).mul_by_nonresidue();
+        let d = d.inverse()?;
+        Some(Fq6::new(t0 * d, t1 * d, t2 * d))
+    }
+}
+
+impl Mul for Fq6 {
+    type Output = Fq6;
+
+    fn mul(self, other: Fq6) -> Fq6 {
+        let (a0, a1, a2) = (self.c0, self.c1, self.c2);
+        let (b0, b1, b2) = (other.c0, other.c1, other.c2);
+        Fq6::new(
+            a0 * b0 + (a1 * b2 + a2 * b1).mul_by_nonresidue(),
+            a0 * b1 + a1 * b0 + (a2 * b2).mul_by_nonresidue(),
+            a0 * b2 + a1 * b1 + a2 * b0,
+        )
+    }
+}
+
+/// c0 + c1 w
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fq12 {
+    pub c0: Fq6,
+    pub c1: Fq6,
+}
+
+impl_componentwise!(Fq12, c0, c1);
+
+impl Fq12 {
+    pub fn new(c0: Fq6, c1: Fq6) -> Fq12 {
+        Fq12 { c0, c1 }
+    }
+
+    /// The p^6 power Frobenius, which maps w to -w.
+    pub fn conjugate(&self) -> Fq12 {
+        Fq12::new(self.c0, -self.c1)
+    }
+}
+
+impl Field for Fq12 {
+    fn zero() -> Fq12 {
+        Fq12::new(Fq6::zero(), Fq6::zero())
+    }
+
+    fn one() -> Fq12 {
+        Fq12::new(Fq6::one(), Fq6::zero())
+    }
+
+    fn inverse(&self) -> Option<Fq12> {
+        let t = (self.c0.square() - self.c1.square().mul_by_v()).inverse()?;
+        Some(Fq12::new(self.c0 * t, -(self.c1 * t)))
+    }
+}
+
+impl Mul for Fq12 {
+    type Output = Fq12;
+
+    fn mul(self, other: Fq12) -> Fq12 {
+        let v0 = self.c0 * other.c0;
+        let v1 = self.c1 * other.c1;
+        Fq12::new(v0 + v1.mul_by_v(), (self.c0 + self.c1) * (other.c0 + other.c1) - v0 - v1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fq_inverse_and_sqrt() {
+        let a = Fq::from_u64(12345);
+        assert_eq!(a * a.inverse().unwrap(), Fq::one());
+        assert_eq!(a.square().sqrt().map(|r| r == a || r == -a), Some(true));
+        // -1 is not a square as p = 3 mod 4
+        assert_eq!((-Fq::one()).sqrt(), None);
+        assert_eq!(Fq::from_bytes(&limbs_to_bytes(&MODULUS)), None);
+        assert_eq!(Fq::from_bytes(&a.to_bytes()), Some(a));
+    }
+
+    #[test]
+    fn tower_inverse_and_sqrt() {
+        let a = Fq2::new(Fq::from_u64(3), Fq::from_u64(7));
+        assert_eq!(a * a.inverse().unwrap(), Fq2::one());
+        let r = a.square().sqrt().unwrap();
+        assert!(r == a || r == -a);
+
+        let b = Fq6::new(a, a.square(), Fq2::one());
+        assert_eq!(b * b.inverse().unwrap(), Fq6::one());
+        let c = Fq12::new(b, b.mul_by_v());
+        assert_eq!(c * c.inverse().unwrap(), Fq12::one());
+    }
+}
@@ -0,0 +1,82 @@
+//! Pure Rust backend over the BN254 curve.
+//!
+//! Signatures live in G1 and keys in G2: a signature is sk H(m) for the
+//! hash H(m) of the message onto G1, and the public key is sk g for a random
+//! generator g of G2. It checks e(sig, g) = e(H(m), pk).
+
+mod field;
+mod curve;
+mod pairing;
+
+use rand::{Rng, OsRng};
+
+use {Error, Keys};
+use self::field::Field;
+use self::curve::{G1, G2};
+
+/// Size of a private key, a scalar below r.
+pub const PRIVATE_KEY_LEN: usize = 32;
+/// Size of a compressed signature, an element of G1.
+pub const SIGNATURE_LEN: usize = 32;
+/// Size of a compressed public key or generator, an element of G2.
+pub const PUBLIC_KEY_LEN: usize = 64;
+
+fn random_scalar<R: Rng>(rng: &mut R) -> [u64; 4] {
+    loop {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        // r < 2^254, drop the top bits so most draws are in range
+        bytes[0] &= 0x3f;
+        if let Some(scalar) = curve::scalar_from_bytes(&bytes) {
+            return scalar;
+        }
+    }
+}
+
+pub fn sign(msg: &[u8], private_key: &[u8]) -> Result<Vec<u8>, Error> {
+    let sk = curve::scalar_from_bytes(private_key).ok_or(Error::InvalidPrivateKey)?;
+    Ok(curve::g1_to_bytes(&curve::hash_to_g1(msg).mul(&sk)))
+}
+
+pub fn verify(msg: &[u8], sig: &[u8], public_key: &[u8], g: &[u8]) -> Result<bool, Error> {
+    let sig = curve::g1_from_bytes(sig).ok_or(Error::InvalidSignature)?;
+    let public_key = curve::g2_from_bytes(public_key).ok_or(Error::InvalidPublicKey)?;
+    let g = curve::g2_from_bytes(g).ok_or(Error::InvalidGenerator)?;
+    let hash: G1 = curve::hash_to_g1(msg);
+    let f = pairing::miller_loop(&sig, &g) * pairing::miller_loop(&hash.neg(), &public_key);
+    Ok(pairing::final_exponentiation(f) == field::Fq12::one())
+}
+
+pub fn key_gen() -> Result<Keys, Error> {
+    let mut rng = OsRng::new().map_err(|_| Error::NoRandomness)?;
+    let sk = random_scalar(&mut rng);
+    let g: G2 = curve::g2_generator().mul(&random_scalar(&mut rng));
+    let public_key = g.mul(&sk);
+    Ok((curve::scalar_to_bytes(&sk).to_vec(), curve::g2_to_bytes(&public_key), curve::g2_to_bytes(&g)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::field::{Fq, Fq2};
+    use super::curve::ORDER;
+
+    #[test]
+    fn reject_points_off_the_group() {
+        // a point of the twist outside the subgroup of order r
+        let mut x = Fq2::one();
+        let y = loop {
+            if let Some(y) = (x.square() * x + curve::g2_b()).sqrt() {
+                break y;
+            }
+            x = x + Fq2::one();
+        };
+        let point = G2::from_affine(x, y);
+        assert!(!point.mul(&ORDER).is_infinity());
+        assert_eq!(curve::g2_from_bytes(&curve::g2_to_bytes(&point)), None);
+
+        // x = 0 has no point on G1, y^2 = 3 is not a square
+        assert_eq!(Fq::from_u64(3).sqrt(), None);
+        assert_eq!(verify(&[1; 32], &[0; SIGNATURE_LEN], &[0; PUBLIC_KEY_LEN], &[0; PUBLIC_KEY_LEN]), Err(Error::InvalidSignature));
+    }
+}
@@ -0,0 +1,92 @@
+//! The reduced Tate pairing G1 x G2 -> Fq12.
+//!
+//! The Miller loop runs over r on the G1 point and evaluates its lines at the
+//! G2 point mapped onto the curve over Fq12 by (x, y) -> (x v, y v w). Vertical
+//! lines and factors in Fq6 are dropped, the final exponentiation sends them
+//! to one.
+
+use super::field::{Field, Fq, Fq2, Fq6, Fq12};
+use super::curve::{G1, G2, ORDER};
+
+/// (p^6 + 1) / r, little-endian limbs.
+const FINAL_EXP: [u64; 20] = [
+    0x5250a54036e3f812, 0xa5635f1596789051, 0xd1138bf54d5bd1d4, 0xa8ce2533be36c7a2,
+    0x94f69f6b84e09bf6, 0x42ad1f5e50ef3644, 0x0fcc420e48c3454c, 0x758e4408ecc9952c,
+    0xc901bf1887c6042c, 0xa733cd65b14bb3b5, 0xdf6d76bdcf51b0d8, 0xca64c0fd82eb59e1,
+    0x1d2e5726e39276a1, 0xc2d1ea74a391cae9, 0x07409206c82d647e, 0x051c6d1aa5afdd17,
+    0xb37f601919667af5, 0x150e578c5084015b, 0xfbdea556c23998e4, 0x000fd14cc52f5b83,
+];
+
+/// Evaluate a y + b x + c at the untwisted (x v, y v w).
+fn line(a: Fq, b: Fq, c: Fq, q: &(Fq2, Fq2)) -> Fq12 {
+    Fq12::new(
+        Fq6::new(Fq2::new(c, Fq::zero()), q.0.scale(b), Fq2::zero()),
+        Fq6::new(Fq2::zero(), q.1.scale(a), Fq2::zero()),
+    )
+}
+
+pub fn miller_loop(p: &G1, q: &G2) -> Fq12 {
+    let (px, py) = match p.to_affine() {
+        Some(p) => p,
+        None => return Fq12::one(),
+    };
+    let q = match q.to_affine() {
+        Some(q) => q,
+        None => return Fq12::one(),
+    };
+    let base = G1::from_affine(px, py);
+    let mut f = Fq12::one();
+    let mut t = base;
+    // r has 254 bits, start below the top one
+    for i in (0..253).rev() {
+        // tangent at T, scaled by 2 Y Z^3
+        let z2 = t.z.square();
+        let x2 = t.x.square();
+        let a = (t.y * z2 * t.z).double();
+        let b = -((x2 * z2).double() + x2 * z2);
+        let c = (x2 * t.x).double() + x2 * t.x - t.y.square().double();
+        f = f.square() * line(a, b, c, &q);
+        t = t.double();
+
+        if (ORDER[i / 64] >> (i % 64)) & 1 == 1 {
+            // chord through T and P, scaled by Z (X - xP Z^2)
+            let z2 = t.z.square();
+            let n = t.y - py * z2 * t.z;
+            let d = t.z * (t.x - px * z2);
+            f = f * line(d, -n, n * px - d * py, &q);
+            t = t.add(&base);
+        }
+    }
+    f
+}
+
+/// Raise to (p^12 - 1) / r, as (p^6 - 1) by conjugation and then (p^6 + 1) / r.
+pub fn final_exponentiation(f: Fq12) -> Fq12 {
+    match f.inverse() {
+        Some(inv) => (f.conjugate() * inv).pow(&FINAL_EXP),
+        None => Fq12::zero(),
+    }
+}
+
+#[cfg(test)]
+pub fn pairing(p: &G1, q: &G2) -> Fq12 {
+    final_exponentiation(miller_loop(p, q))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::curve::{g2_generator, hash_to_g1};
+
+    #[test]
+    fn bilinear() {
+        let p = hash_to_g1(b"sleepy");
+        let q = g2_generator();
+        let e = pairing(&p, &q);
+        assert!(e != Fq12::one());
+        assert_eq!(e.pow(&ORDER), Fq12::one());
+        assert_eq!(pairing(&p.mul(&[6, 0, 0, 0]), &q), pairing(&p, &q.mul(&[6, 0, 0, 0])));
+        assert_eq!(pairing(&p.mul(&[2, 0, 0, 0]), &q.mul(&[3, 0, 0, 0])), e.pow(&[6]));
+        assert_eq!(pairing(&p.neg(), &q) * e, Fq12::one());
+    }
+}
@@ -1,68 +1,105 @@
-#[link(name = "pbc")]
-#[link(name = "gmp")]
-extern "C" {
-    pub fn sign_c(out: *mut u8, out_len: *mut usize, msg: *mut u8, msg_len: usize, data: *mut u8);
-    pub fn verify_c(msg: *mut u8, msg_len: usize, data_s: *mut u8, data_g: *mut u8, data_p: *mut u8) -> i32;
-    pub fn key_gen_c(out_sk: *mut u8, sk_len: *mut usize, out_pk: *mut u8, pk_len: *mut usize, out_g: *mut u8, g_len: *mut usize);
-}
+//! BLS signatures for the time proofs.
+//!
+//! The default backend is written in Rust over the BN254 curve. The `pbc`
+//! feature switches to the C `pbc` library over its type F curve instead, the
+//! backend of earlier versions, to keep their keys and proofs valid. Keys and
+//! signatures of one backend are not valid for the other.
 
-//sign the msg, only msg[0..20] is used.
-pub fn sign(mut msg: Vec<u8>, mut private_key: Vec<u8>) -> Vec<u8> {
-    unsafe {
-        let mut sig = Vec::with_capacity(30);
-        let mut sig_len = 0usize;
-        let msg_len = msg.len();        
+#[cfg(not(feature = "pbc"))]
+extern crate rand;
+#[cfg(not(feature = "pbc"))]
+extern crate tiny_keccak;
 
-        sign_c(sig.as_mut_ptr(), &mut sig_len, msg.as_mut_ptr(), msg_len, private_key.as_mut_ptr());
+use std::error;
+use std::fmt;
 
-        sig.set_len(sig_len);
-        sig 
-    }
-}
+#[cfg(not(feature = "pbc"))]
+mod bn254;
+#[cfg(not(feature = "pbc"))]
+use bn254 as backend;
+
+#[cfg(feature = "pbc")]
+mod pbc;
+#[cfg(feature = "pbc")]
+use pbc as backend;
 
-pub fn verify(mut msg: Vec<u8>, mut sig: Vec<u8>, mut public_key: Vec<u8>, mut g: Vec<u8>) -> bool {
-    unsafe {
-        let msg_len = msg.len();
+pub use backend::{PRIVATE_KEY_LEN, SIGNATURE_LEN, PUBLIC_KEY_LEN};
 
-        let r = verify_c(msg.as_mut_ptr(), msg_len, sig.as_mut_ptr(), g.as_mut_ptr(), public_key.as_mut_ptr());
+/// A private key, its public key and the generator of the public key.
+pub type Keys = (Vec<u8>, Vec<u8>, Vec<u8>);
 
-        r != 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    InvalidPrivateKey,
+    InvalidPublicKey,
+    InvalidGenerator,
+    InvalidSignature,
+    /// The OS random source is not available.
+    NoRandomness,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match *self {
+            Error::InvalidPrivateKey => "Invalid private key",
+            Error::InvalidPublicKey => "Invalid public key",
+            Error::InvalidGenerator => "Invalid generator",
+            Error::InvalidSignature => "Invalid signature",
+            Error::NoRandomness => "No random source",
+        };
+        f.write_fmt(format_args!("BLS error ({})", msg))
     }
 }
 
-pub fn key_gen() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
-    unsafe {
-        let mut private_key = Vec::with_capacity(30);
-        let mut private_len = 0usize;
-        let mut public_key = Vec::with_capacity(40);
-        let mut public_len = 0usize;
-        let mut g = Vec::with_capacity(40);
-        let mut g_len = 0usize;
-
-        key_gen_c(private_key.as_mut_ptr(), &mut private_len, public_key.as_mut_ptr(), &mut public_len, g.as_mut_ptr(), &mut g_len);
-
-        private_key.set_len(private_len);
-        public_key.set_len(public_len);
-        g.set_len(g_len);
-        (private_key, public_key, g)
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "BLS error"
     }
 }
 
+/// Sign `msg` with `private_key`.
+pub fn sign(msg: &[u8], private_key: &[u8]) -> Result<Vec<u8>, Error> {
+    backend::sign(msg, private_key)
+}
+
+/// Verify the signature of `msg` by the owner of `public_key` for generator `g`.
+/// Malformed inputs are errors, a well-formed signature that doesn't match is `Ok(false)`.
+pub fn verify(msg: &[u8], sig: &[u8], public_key: &[u8], g: &[u8]) -> Result<bool, Error> {
+    backend::verify(msg, sig, public_key, g)
+}
+
+/// Generate a private key, a public key and its generator.
+pub fn key_gen() -> Result<Keys, Error> {
+    backend::key_gen()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_sign_and_verify() {
-        let (private_key, public_key, g) = key_gen();
+        let (private_key, public_key, g) = key_gen().unwrap();
+        assert_eq!(private_key.len(), PRIVATE_KEY_LEN);
+        assert_eq!(public_key.len(), PUBLIC_KEY_LEN);
+        assert_eq!(g.len(), PUBLIC_KEY_LEN);
         let msg = vec![1; 20];
-        let sig = sign(msg.clone(), private_key.clone());
-        let r = verify(msg, sig.clone(), public_key.clone(), g.clone());
-        assert_eq!(r, true);
+        let sig = sign(&msg, &private_key).unwrap();
+        assert_eq!(sig.len(), SIGNATURE_LEN);
+        let r = verify(&msg, &sig, &public_key, &g).unwrap();
+        assert!(r);
 
         let msg = vec![2; 20];
-        let r = verify(msg, sig.clone(), public_key.clone(), g.clone());
-        assert_eq!(r, false);
+        let r = verify(&msg, &sig, &public_key, &g).unwrap();
+        assert!(!r);
+
+        let (_, other_key, other_g) = key_gen().unwrap();
+        assert_eq!(verify(&[1; 20], &sig, &other_key, &other_g), Ok(false));
+    }
+
+    #[test]
+    fn test_invalid_private_key() {
+        assert_eq!(sign(&[1; 20], &[]), Err(Error::InvalidPrivateKey));
+        assert_eq!(sign(&[1; 20], &[0xff; PRIVATE_KEY_LEN + 1]), Err(Error::InvalidPrivateKey));
     }
-    
 }
@@ -0,0 +1,79 @@
+//! Compatibility backend over the C `pbc` library and its type F pairing,
+//! which keeps the keys and proofs made before the pure Rust backend valid.
+//!
+//! Inputs are checked against the element sizes before they are handed to C,
+//! and outputs are written into buffers larger than any element.
+
+use {Error, Keys};
+
+/// Size of a private key, an element of Zr.
+pub const PRIVATE_KEY_LEN: usize = 20;
+/// Size of a compressed signature, an element of G1.
+pub const SIGNATURE_LEN: usize = 21;
+/// Size of a compressed public key or generator, an element of G2.
+pub const PUBLIC_KEY_LEN: usize = 41;
+
+/// Scratch space for an element written by C.
+const MAX_ELEMENT_LEN: usize = 128;
+
+#[link(name = "pbc")]
+#[link(name = "gmp")]
+extern "C" {
+    fn sign_c(out: *mut u8, out_len: *mut usize, msg: *const u8, msg_len: usize, data: *const u8);
+    fn verify_c(msg: *const u8, msg_len: usize, data_s: *const u8, data_g: *const u8, data_p: *const u8) -> i32;
+    fn key_gen_c(out_sk: *mut u8, sk_len: *mut usize, out_pk: *mut u8, pk_len: *mut usize, out_g: *mut u8, g_len: *mut usize);
+}
+
+fn check_len(data: &[u8], len: usize, err: Error) -> Result<(), Error> {
+    if data.len() == len { Ok(()) } else { Err(err) }
+}
+
+pub fn sign(msg: &[u8], private_key: &[u8]) -> Result<Vec<u8>, Error> {
+    check_len(private_key, PRIVATE_KEY_LEN, Error::InvalidPrivateKey)?;
+    let mut sig = vec![0; MAX_ELEMENT_LEN];
+    let mut sig_len = 0usize;
+    unsafe {
+        sign_c(sig.as_mut_ptr(), &mut sig_len, msg.as_ptr(), msg.len(), private_key.as_ptr());
+    }
+    sig.truncate(sig_len);
+    Ok(sig)
+}
+
+pub fn verify(msg: &[u8], sig: &[u8], public_key: &[u8], g: &[u8]) -> Result<bool, Error> {
+    check_len(sig, SIGNATURE_LEN, Error::InvalidSignature)?;
+    check_len(public_key, PUBLIC_KEY_LEN, Error::InvalidPublicKey)?;
+    check_len(g, PUBLIC_KEY_LEN, Error::InvalidGenerator)?;
+    let r = unsafe { verify_c(msg.as_ptr(), msg.len(), sig.as_ptr(), g.as_ptr(), public_key.as_ptr()) };
+    Ok(r != 0)
+}
+
+pub fn key_gen() -> Result<Keys, Error> {
+    let mut private_key = vec![0; MAX_ELEMENT_LEN];
+    let mut private_len = 0usize;
+    let mut public_key = vec![0; MAX_ELEMENT_LEN];
+    let mut public_len = 0usize;
+    let mut g = vec![0; MAX_ELEMENT_LEN];
+    let mut g_len = 0usize;
+    unsafe {
+        key_gen_c(private_key.as_mut_ptr(), &mut private_len, public_key.as_mut_ptr(), &mut public_len, g.as_mut_ptr(), &mut g_len);
+    }
+    private_key.truncate(private_len);
+    public_key.truncate(public_len);
+    g.truncate(g_len);
+    Ok((private_key, public_key, g))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_lengths() {
+        let msg = vec![1; 20];
+        assert_eq!(sign(&msg, &[1; 10]), Err(Error::InvalidPrivateKey));
+        let key = [1; PUBLIC_KEY_LEN];
+        assert_eq!(verify(&msg, &[], &key, &key), Err(Error::InvalidSignature));
+        assert_eq!(verify(&msg, &[1; SIGNATURE_LEN], &key[1..], &key), Err(Error::InvalidPublicKey));
+        assert_eq!(verify(&msg, &[1; SIGNATURE_LEN], &key, &[]), Err(Error::InvalidGenerator));
+    }
+}
@@ -1,4 +1,6 @@
-use common::*;
+use std::fmt;
+use H256;
+use bytes::ToPretty;
 use hashdb::*;
 use rlp::*;
 use super::node::{Node, OwnedNode, NodeKey};
@@ -15,7 +17,7 @@ use super::{AVL, AVLItem, AVLError, AVLIterator, Query};
 /// extern crate util;
 ///
 /// use util::avl::*;
-/// use util::hashdb::*;
+/// use util::{HashDB, DBValue};
 /// use util::memorydb::*;
 /// use util::hash::*;
 ///
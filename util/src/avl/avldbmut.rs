@@ -225,7 +225,7 @@ impl<'a> Index<&'a StorageHandle> for NodeStorage {
 /// extern crate util;
 ///
 /// use util::avl::*;
-/// use util::hashdb::*;
+/// use util::{HashDB, DBValue};
 /// use util::memorydb::*;
 /// use util::hash::*;
 ///
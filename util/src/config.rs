@@ -3,13 +3,12 @@ extern crate toml;
 use std::io::prelude::*;
 use std::fs::File;
 use std::io::BufReader;
-use {H256, H512, U256};
+use {Address, H256, H512};
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
-use std::thread;
-use std::sync::mpsc;
+use std::sync::Arc;
 use time;
-use ntp;
+use timesource::{TimeSource, TimeSourceKind, NtpTime, SystemTime};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -18,7 +17,11 @@ pub struct Config {
     pub max_peer: u64,
     pub steps: u64,
     pub nps: u64,
+    /// Not needed by an observer.
+    #[serde(default)]
     pub miner_private_key: Vec<u8>,
+    /// Key of the block signatures and of the handshake with peers, also
+    /// needed by an observer so that its peers can authenticate it.
     pub signer_private_key: H256,
     pub peers: Vec<PeerConfig>,
     pub keygroups: Vec<KeyGroup>,
@@ -26,12 +29,146 @@ pub struct Config {
     pub start_time: u64,
     pub ntp_servers: Vec<String>,
     pub buffer_size: u64,
+    #[serde(default)]
+    pub time_source: TimeSourceKind,
+    /// Seconds between two NTP syncs.
+    #[serde(default = "default_ntp_interval")]
+    pub ntp_interval: u64,
+    /// Port of the JSON-RPC endpoint, disabled if not set.
+    pub rpc_port: Option<u64>,
+    /// Encrypt the sessions with peers.
+    #[serde(default)]
+    pub encryption: bool,
+    #[serde(default)]
+    pub tx_pool: PoolConfig,
+    /// Rule to pick the head among blocks of the same height,
+    /// `lowest_difficulty` or `earliest_timestamp`.
+    #[serde(default = "default_fork_choice")]
+    pub fork_choice: String,
+    /// Blocks on top of a block for it to be final, `buffer_size` if not set.
+    pub confirmations: Option<u64>,
+    /// Keep the bodies of only this many recent blocks and delete forks below
+    /// the finalized block, everything is kept if not set. The state is kept
+    /// in full either way.
+    pub pruning: Option<u64>,
+    /// Path of the genesis spec, which overrides `start_time`, `epoch_len`,
+    /// `steps`, `nps` and `keygroups`.
+    pub genesis: Option<String>,
+    /// Retarget the difficulty of every epoch to the block intervals of the
+    /// epoch before, instead of only to the number of validators.
+    #[serde(default)]
+    pub difficulty_adjustment: bool,
+    /// Produce blocks, a node without it only follows the chain until
+    /// mining is resumed.
+    #[serde(default = "default_mining")]
+    pub mining: bool,
+    /// Follow and serve the chain without mining keys and without ever mining.
+    #[serde(default)]
+    pub observer: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct PoolConfig {
+    pub capacity: usize,
+    pub package_limit: usize,
+    /// One of `fifo`, `priority` or `vip`.
+    pub strategy: String,
+    /// Senders whose transactions are packaged first with the `vip` strategy.
+    #[serde(default)]
+    pub vip_public_keys: Vec<H512>,
+    /// Max number of pending transactions.
+    #[serde(default = "default_pool_max_count")]
+    pub max_count: usize,
+    /// Max memory used by the pending transactions, in bytes.
+    #[serde(default = "default_pool_max_bytes")]
+    pub max_bytes: usize,
+    /// Max number of pending transactions of one sender.
+    #[serde(default = "default_pool_max_per_sender")]
+    pub max_per_sender: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            capacity: 1000,
+            package_limit: 300,
+            strategy: "fifo".to_owned(),
+            vip_public_keys: Vec::new(),
+            max_count: default_pool_max_count(),
+            max_bytes: default_pool_max_bytes(),
+            max_per_sender: default_pool_max_per_sender(),
+        }
+    }
+}
+
+fn default_pool_max_count() -> usize {
+    20000
+}
+
+fn default_pool_max_bytes() -> usize {
+    32 * 1024 * 1024
+}
+
+fn default_pool_max_per_sender() -> usize {
+    1000
+}
+
+fn default_fork_choice() -> String {
+    "lowest_difficulty".to_owned()
+}
+
+fn default_mining() -> bool {
+    true
+}
+
+fn default_ntp_interval() -> u64 {
+    60
+}
+
+#[derive(Debug)]
 pub struct SleepyConfig {
     pub config: Config,
     pub public_keys: HashMap<H512, (Vec<u8>, Vec<u8>)>,
+    pub time_source: Arc<TimeSource>,
+    /// The genesis spec, `None` for the empty genesis at `start_time`.
+    pub genesis: Option<GenesisSpec>,
+}
+
+/// Genesis block and chain parameters every node of a chain has to agree on.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GenesisSpec {
+    pub timestamp: u64,
+    pub epoch_len: u64,
+    pub steps: u64,
+    pub nps: u64,
+    pub keygroups: Vec<KeyGroup>,
+    /// Initial storage of the accounts.
+    #[serde(default)]
+    pub alloc: Vec<Alloc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Alloc {
+    pub address: Address,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl GenesisSpec {
+    pub fn new(path: &str) -> Self {
+        let mut content = String::new();
+        File::open(path).unwrap().read_to_string(&mut content).unwrap();
+        toml::from_str(&content).unwrap()
+    }
+
+    /// Replace the chain parameters of `config` by those of the spec.
+    pub fn apply(&self, config: &mut Config) {
+        config.start_time = self.timestamp;
+        config.epoch_len = self.epoch_len;
+        config.steps = self.steps;
+        config.nps = self.nps;
+        config.keygroups = self.keygroups.clone();
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,6 +176,12 @@ pub struct PeerConfig {
     pub id_card: u32,
     pub ip: String,
     pub port: u64,
+    /// Key the peer signs the handshake with, must be one of the keygroups
+    /// unless the peer is an observer.
+    pub signer_public_key: H512,
+    /// The peer only follows the chain.
+    #[serde(default)]
+    pub observer: bool,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -70,7 +213,27 @@ impl SleepyConfig {
         let mut fconfig = BufReader::new(config_file);
         let mut content = String::new();
         fconfig.read_to_string(&mut content).unwrap();
-        let config: Config = toml::from_str(&content).unwrap();
+        let mut config: Config = toml::from_str(&content).unwrap();
+        if config.signer_private_key.is_zero() {
+            panic!("signer_private_key is required");
+        }
+        if !config.observer && config.miner_private_key.is_empty() {
+            panic!("miner_private_key is required unless observer is set");
+        }
+        let genesis = config.genesis.as_ref().map(|path| GenesisSpec::new(path));
+        if let Some(ref spec) = genesis {
+            spec.apply(&mut config);
+        }
+        let time_source: Arc<TimeSource> = match config.time_source {
+            TimeSourceKind::Ntp => NtpTime::start(config.ntp_servers.clone(), config.ntp_interval),
+            TimeSourceKind::System => Arc::new(SystemTime),
+        };
+        let mut config = SleepyConfig::with_time_source(config, time_source);
+        config.genesis = genesis;
+        config
+    }
+
+    pub fn with_time_source(config: Config, time_source: Arc<TimeSource>) -> Self {
         let mut public_keys = HashMap::new();
 
         for v in config.keygroups.clone() {
@@ -81,6 +244,8 @@ impl SleepyConfig {
         SleepyConfig {
             config: config,
             public_keys: public_keys,
+            time_source: time_source,
+            genesis: None,
         }
     }
 
@@ -104,8 +269,9 @@ impl SleepyConfig {
         self.signer_private_key
     }
 
-    pub fn get_difficulty(&self) -> U256 {
-        (U256::max_value() / U256::from((self.max_peer + 1) * self.steps * self.nps)).into()
+    /// Confirmation depth of a final block.
+    pub fn get_confirmations(&self) -> u64 {
+        self.confirmations.unwrap_or(self.buffer_size)
     }
 
     pub fn get_proof_pub(&self, sign_key: &H512) -> Option<(Vec<u8>, Vec<u8>)> {
@@ -124,42 +290,13 @@ impl SleepyConfig {
         (now.sec * self.nps as i64 + now.nsec as i64 / (1000000000 / self.nps) as i64) as u64
     }
 
-    pub fn ntp_now(&self) -> Option<u64> {
-        let now = self.ntp_timestamp();
-        if now == 0 {
-            return None;
-        }
-        Some((now / (1000000000 / self.nps) as i64) as u64)
-    }
-
-    pub fn ntp_timestamp(&self) ->i64 {
-        let (tx, rx) = mpsc::channel();
-        let address = self.ntp_servers.clone();
-        let len = address.len();
-        for addr in address {
-            let tx = tx.clone();
-
-            thread::spawn(move || {
-                let time = match ntp::request(addr) {
-                    Ok(res) => {
-                        let t = time::Timespec::from(res.transmit_time);
-                        t.sec * 1000000000 + t.nsec as i64
-                    },
-                    _ => 0,
-                };
-                let _ = tx.send(time);
-            });
-        }
-        
-        let mut r: i64 = 0;
-        for _ in 0..len {
-            if let Ok(t) = rx.recv() {
-                if t != 0 && r == 0 {
-                    r = t
-                }
-            }
-        }
-        r
+    pub fn set_time_source(&mut self, time_source: Arc<TimeSource>) {
+        self.time_source = time_source;
+    }
+
+    /// Current time in ticks, `None` if the time source does not know it.
+    pub fn now(&self) -> Option<u64> {
+        self.time_source.now().map(|ns| (ns / (1000000000 / self.nps) as i64) as u64)
     }
 }
 
@@ -167,8 +304,7 @@ impl SleepyConfig {
 mod test {
     use super::*;
     extern crate toml;
-    use std::thread;
-    use std::time::Duration;
+    use timesource::MockTime;
     #[test]
     fn basics() {
         let toml = r#"
@@ -183,15 +319,23 @@ mod test {
             signer_private_key = "5a39ed1020c04d4d84539975b893a4e7c53eab6c2965db8bc3468093a31bc5ae"
             ntp_servers = ["s1a.time.edu.cn:123", "cn.ntp.org.cn:123" ]
             buffer_size = 5
+            time_source = "system"
+
+            [tx_pool]
+            capacity = 1000
+            package_limit = 300
+            strategy = "priority"
             
             [[peers]]
             id_card = 1
             ip = "127.0.0.1"
             port = 40001
+            signer_public_key = "5a39ed1020c04d4d84539975b893a4e7c53eab6c2965db8bc3468093a31bc5ae5a39ed1020c04d4d84539975b893a4e7c53eab6c2965db8bc3468093a31bc5ae"
             [[peers]]
             id_card = 2
             ip = "127.0.0.1"
             port = 40002
+            signer_public_key = "5a39ed1020c04d4d84539975b893a4e7c53eab6c2965db8bc3468093a31bc5ae5a39ed1020c04d4d84539975b893a4e7c53eab6c2965db8bc3468093a31bc5af"
             [[keygroups]]
             proof_public_key = [5, 187, 13, 170, 167, 224, 60, 147, 202, 19, 224, 0, 123, 201, 193, 8, 80, 105, 212, 162, 5, 103, 50, 145, 212, 129, 226, 7, 133, 209, 205, 106, 25, 243, 195, 27, 250, 97, 33, 164, 1]
             proof_public_g = [26, 143, 4, 165, 28, 50, 23, 127, 123, 48, 213, 125, 157, 223, 45, 63, 193, 95, 249, 215, 27, 71, 102, 178, 229, 66, 7, 46, 227, 238, 184, 125, 152, 61, 121, 252, 4, 156, 131, 163, 0]
@@ -203,13 +347,38 @@ mod test {
         "#;
 
         let value: Config = toml::from_str(toml).unwrap();
-        let config = SleepyConfig {config: value, public_keys: HashMap::new()};
+        assert_eq!(value.time_source, TimeSourceKind::System);
+        assert_eq!(value.ntp_interval, 60);
+        assert_eq!(value.tx_pool.strategy, "priority");
+        assert_eq!(value.tx_pool.capacity, 1000);
+        let mut config = SleepyConfig::with_time_source(value, Arc::new(SystemTime));
         println!("{:?}", config);
         assert_eq!(config.port, 40000);
+        assert!(config.now().is_some());
 
-        let _ = config.ntp_now();
-        thread::sleep(Duration::from_millis(100));
-        let _ = config.ntp_now();
-        // assert_eq!(t1 - t, 1);
+        let mock = Arc::new(MockTime::new(1000000000));
+        config.set_time_source(mock.clone());
+        assert_eq!(config.now(), Some(10));
+        mock.advance(100000000);
+        assert_eq!(config.now(), Some(11));
+
+        let spec = r#"
+            timestamp = 7
+            epoch_len = 20
+            steps = 3
+            nps = 100
+            keygroups = []
+
+            [[alloc]]
+            address = "0000000000000000000000000000000000000001"
+            key = [1]
+            value = [2, 3]
+        "#;
+        let spec: GenesisSpec = toml::from_str(spec).unwrap();
+        assert_eq!(spec.alloc[0].address, Address::from(1));
+        spec.apply(&mut config);
+        assert_eq!(config.start_time(), 7);
+        assert_eq!(config.epoch_len, 20);
+        assert!(config.get_keygroups().is_empty());
     }
 }
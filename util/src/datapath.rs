@@ -55,6 +55,13 @@ impl DataPath {
         return data_path + "/proof.bin";
     }
 
+    /// journal of the pending transactions
+    pub fn txpool_path() -> String {
+        let data_path = DataPath::root_node_path();
+
+        return data_path + "/txpool";
+    }
+
     /// wal log path
     pub fn wal_path() -> String {
         let data_path = DataPath::root_node_path();
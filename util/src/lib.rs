@@ -8,6 +8,9 @@ extern crate rlp;
 extern crate heapsize;
 extern crate ansi_term;
 extern crate ntp;
+extern crate ethcore_bytes as bytes;
+#[macro_use]
+extern crate log;
 
 extern crate serde;
 #[macro_use]
@@ -26,7 +29,10 @@ pub mod error;
 pub mod sha3;
 pub mod merklehash;
 pub mod config;
+pub mod timesource;
 pub mod datapath;
+pub mod memorydb;
+pub mod avl;
 
 pub use hashdb::*;
 pub use merklehash::*;
@@ -34,6 +40,7 @@ pub use error::*;
 pub use sha3::*;
 pub use bigint::*;
 pub use bigint::hash;
+pub use bytes::Bytes;
 
 pub use ansi_term::{Colour, Style};
 pub use heapsize::HeapSizeOf;
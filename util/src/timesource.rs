@@ -0,0 +1,186 @@
+//! Sources of the current time.
+//!
+//! Times are nanoseconds since the unix epoch. `SleepyConfig` turns them into
+//! ticks of `1 / nps` seconds.
+
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use parking_lot::{Mutex, RwLock};
+use time;
+use ntp;
+
+pub trait TimeSource: Send + Sync + fmt::Debug {
+    /// Current time in nanoseconds, `None` if it is not known.
+    fn now(&self) -> Option<i64>;
+}
+
+/// Which time source a node uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSourceKind {
+    Ntp,
+    System,
+}
+
+impl Default for TimeSourceKind {
+    fn default() -> Self {
+        TimeSourceKind::Ntp
+    }
+}
+
+fn system_ns() -> i64 {
+    let now = time::get_time();
+    now.sec * 1000000000 + now.nsec as i64
+}
+
+/// The local system clock.
+#[derive(Debug, Default)]
+pub struct SystemTime;
+
+impl TimeSource for SystemTime {
+    fn now(&self) -> Option<i64> {
+        Some(system_ns())
+    }
+}
+
+/// Ask all `servers` in parallel and return the first answer.
+pub fn ntp_request(servers: &[String]) -> Option<i64> {
+    let (tx, rx) = mpsc::channel();
+    for addr in servers.iter().cloned() {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let time = ntp::request(addr).ok().map(|res| {
+                let t = time::Timespec::from(res.transmit_time);
+                t.sec * 1000000000 + t.nsec as i64
+            });
+            let _ = tx.send(time);
+        });
+    }
+
+    for _ in 0..servers.len() {
+        if let Ok(Some(t)) = rx.recv() {
+            return Some(t);
+        }
+    }
+    None
+}
+
+/// System clock corrected by the offset to the NTP servers.
+///
+/// The offset is refreshed by a background thread, so reading the time never
+/// waits for the network.
+#[derive(Debug)]
+pub struct NtpTime {
+    servers: Vec<String>,
+    /// NTP time minus system time, in nanoseconds.
+    offset: RwLock<Option<i64>>,
+}
+
+impl NtpTime {
+    /// Sync once, then resync every `interval` seconds in the background.
+    pub fn start(servers: Vec<String>, interval: u64) -> Arc<Self> {
+        let ntp = Arc::new(NtpTime {
+            servers: servers,
+            offset: RwLock::new(None),
+        });
+        ntp.sync();
+
+        let subtask = ntp.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(interval));
+            subtask.sync();
+        });
+        ntp
+    }
+
+    /// Query the servers and update the offset. Keeps the old offset on failure.
+    pub fn sync(&self) -> bool {
+        let before = system_ns();
+        match ntp_request(&self.servers) {
+            Some(t) => {
+                // assume the answer was sent half way through the request
+                let local = before + (system_ns() - before) / 2;
+                let offset = t - local;
+                let old = mem::replace(&mut *self.offset.write(), Some(offset));
+                if let Some(old) = old {
+                    trace!("ntp offset {} ms, drift {} ms", offset / 1000000, (offset - old) / 1000000);
+                } else {
+                    info!("ntp offset {} ms", offset / 1000000);
+                }
+                true
+            }
+            None => {
+                warn!("ntp sync failed");
+                false
+            }
+        }
+    }
+
+    /// Offset of the system clock to NTP in nanoseconds.
+    pub fn offset(&self) -> Option<i64> {
+        *self.offset.read()
+    }
+}
+
+impl TimeSource for NtpTime {
+    fn now(&self) -> Option<i64> {
+        self.offset().map(|offset| system_ns() + offset)
+    }
+}
+
+/// A clock which only moves when told to, for tests.
+#[derive(Debug, Default)]
+pub struct MockTime {
+    now: Mutex<i64>,
+}
+
+impl MockTime {
+    pub fn new(now: i64) -> Self {
+        MockTime { now: Mutex::new(now) }
+    }
+
+    pub fn set(&self, now: i64) {
+        *self.now.lock() = now;
+    }
+
+    pub fn advance(&self, ns: i64) {
+        *self.now.lock() += ns;
+    }
+}
+
+impl TimeSource for MockTime {
+    fn now(&self) -> Option<i64> {
+        Some(*self.now.lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_time() {
+        let mock = MockTime::new(1000);
+        assert_eq!(mock.now(), Some(1000));
+        mock.advance(500);
+        assert_eq!(mock.now(), Some(1500));
+        mock.set(10);
+        assert_eq!(mock.now(), Some(10));
+    }
+
+    #[test]
+    fn ntp_without_servers() {
+        let ntp = NtpTime {
+            servers: Vec::new(),
+            offset: RwLock::new(None),
+        };
+        assert!(!ntp.sync());
+        assert_eq!(ntp.now(), None);
+        *ntp.offset.write() = Some(0);
+        assert!(ntp.now().unwrap() - SystemTime.now().unwrap() < 1000000000);
+    }
+}